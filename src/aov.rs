@@ -0,0 +1,154 @@
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::material::MaterialType;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::rc::Rc;
+
+// Surface first hit by a camera ray, as recorded by the integrator.
+#[derive(Clone)]
+pub struct FirstHit {
+    // Index of the object in the scene world.
+    pub object: usize,
+    // Material of the object, mixes unresolved.
+    pub material: Rc<MaterialType>,
+    pub rec: HitRecord,
+}
+
+// What an integrator reports about one camera path besides its radiance.
+#[derive(Default)]
+pub struct PathAovs {
+    pub first_hit: Option<FirstHit>,
+    // Radiance from each light group, indexed like `scene.group_names`.
+    pub groups: Vec<Color>,
+}
+
+impl PathAovs {
+    pub fn new(group_count: usize) -> Self {
+        Self {
+            first_hit: None,
+            groups: vec![Color::new(0.0, 0.0, 0.0); group_count],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.first_hit = None;
+        self.groups.fill(Color::new(0.0, 0.0, 0.0));
+    }
+}
+
+// Arbitrary output variables: passes rendered alongside the beauty image for
+// compositing. Depth, normal, albedo, uv and the ids come from the first hit
+// of each camera ray, and each light group gets the radiance of its lights.
+// Every pass is written as a floating point PFM image.
+pub struct Aovs {
+    width: usize,
+    height: usize,
+    // Averaged over the samples of a pixel.
+    normal: Vec<Color>,
+    albedo: Vec<Color>,
+    uv: Vec<Color>,
+    light_groups: Vec<Vec<Color>>,
+    group_names: Vec<String>,
+    // Taken from the first camera ray of a pixel, as averaging the depths or
+    // ids of different surfaces gives meaningless values. Misses have zero
+    // depth and id.
+    depth: Vec<f64>,
+    object_id: Vec<usize>,
+    material_id: Vec<usize>,
+    // Materials numbered from one in the order they're first seen.
+    material_ids: HashMap<*const MaterialType, usize>,
+}
+
+impl Aovs {
+    pub fn new(width: u32, height: u32, scene: &Scene) -> Self {
+        let (width, height) = (width as usize, height as usize);
+        let black = vec![Color::new(0.0, 0.0, 0.0); width * height];
+        Self {
+            width,
+            height,
+            normal: black.clone(),
+            albedo: black.clone(),
+            uv: black.clone(),
+            light_groups: vec![black; scene.group_names.len()],
+            group_names: scene.group_names.clone(),
+            depth: vec![0.0; width * height],
+            object_id: vec![0; width * height],
+            material_id: vec![0; width * height],
+            material_ids: HashMap::new(),
+        }
+    }
+
+    pub fn group_count(&self) -> usize {
+        self.group_names.len()
+    }
+
+    // Records the camera path traced along `r` through `pixel`, counted in
+    // rows from the top of the image.
+    pub fn add_sample(&mut self, pixel: usize, first: bool, r: &Ray, path: &PathAovs) {
+        for (pass, c) in self.light_groups.iter_mut().zip(&path.groups) {
+            pass[pixel] += *c;
+        }
+
+        let hit = match &path.first_hit {
+            Some(hit) => hit,
+            None => return,
+        };
+        let rec = &hit.rec;
+        let n = rec.outward_normal();
+        self.normal[pixel] += Color::new(n.x(), n.y(), n.z());
+        self.albedo[pixel] += hit.material.albedo_at(r, rec);
+        self.uv[pixel] += Color::new(rec.u, rec.v, 0.0);
+
+        if first {
+            let next_id = self.material_ids.len() + 1;
+            self.depth[pixel] = rec.t * r.direction().norm();
+            self.object_id[pixel] = hit.object + 1;
+            self.material_id[pixel] = *self
+                .material_ids
+                .entry(Rc::as_ptr(&hit.material))
+                .or_insert(next_id);
+        }
+    }
+
+    // Writes every pass to `<prefix>_<pass>.pfm`, light groups as
+    // `<prefix>_light_<group>.pfm`.
+    pub fn write(&self, prefix: &str, samples_per_pixel: u32) -> std::io::Result<()> {
+        let scale = 1.0 / samples_per_pixel as f64;
+        let grey = |v: f64| Color::new(v, v, v);
+        let averaged = |pass: &[Color]| pass.iter().map(|c| *c * scale).collect::<Vec<_>>();
+
+        self.write_pass(
+            prefix,
+            "depth",
+            &self.depth.iter().map(|&d| grey(d)).collect::<Vec<_>>(),
+        )?;
+        self.write_pass(prefix, "normal", &averaged(&self.normal))?;
+        self.write_pass(prefix, "albedo", &averaged(&self.albedo))?;
+        self.write_pass(prefix, "uv", &averaged(&self.uv))?;
+        let ids = |pass: &[usize]| pass.iter().map(|&id| grey(id as f64)).collect::<Vec<_>>();
+        self.write_pass(prefix, "object_id", &ids(&self.object_id))?;
+        self.write_pass(prefix, "material_id", &ids(&self.material_id))?;
+        for (name, pass) in self.group_names.iter().zip(&self.light_groups) {
+            self.write_pass(prefix, &format!("light_{}", name), &averaged(pass))?;
+        }
+        Ok(())
+    }
+
+    // PFM stores little endian floats, rows from the bottom of the image.
+    fn write_pass(&self, prefix: &str, name: &str, pixels: &[Color]) -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(format!("{}_{}.pfm", prefix, name))?);
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in pixels.chunks(self.width).rev() {
+            for c in row {
+                for v in [c.r(), c.g(), c.b()] {
+                    out.write_all(&(v as f32).to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
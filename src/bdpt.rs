@@ -0,0 +1,567 @@
+// Bidirectional path tracing (Veach 1997, following the structure of pbrt):
+// a camera subpath and a light subpath are traced for every sample and each
+// pair of their vertices is connected, the resulting strategies being
+// combined with the balance heuristic.
+//
+// The pinhole camera can't be hit by rays, so strategies connecting light
+// subpaths directly to the camera are left out. Lights at infinity don't start
+// light subpaths and are handled like in the path tracer, by escaping camera
+// rays and light sampling. Participating media are ignored.
+use crate::aov::{FirstHit, PathAovs};
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::integrator::{background_by_light, credit, hit_first, russian_roulette};
+use crate::light::{LightSampling, LightType};
+use crate::material::{LightReflection, MaterialType};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::utils::power_heuristic;
+use crate::vec::Vec3;
+use std::rc::Rc;
+
+#[derive(Clone)]
+enum VertexKind {
+    // Keeping the surface its ray hit first for the AOVs.
+    Camera(Option<FirstHit>),
+    // Point on the light at this index in the scene lights.
+    Light(usize),
+    // Material scattering at the surface, with mixes resolved, and the light
+    // owning the surface when it is an emitter.
+    Surface(Rc<MaterialType>, Option<usize>),
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    // Position and normal, the normal being zero for the camera and point lights.
+    rec: HitRecord,
+    // Direction of the ray that reached the vertex.
+    incoming: Vec3,
+    // Path throughput up to the vertex.
+    beta: Color,
+    // Area pdfs of sampling the vertex from its predecessor on the subpath,
+    // and from its successor when the path is traced the other way.
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    // Scattered specularly, so it can't be connected to.
+    delta: bool,
+}
+
+impl Vertex {
+    fn new(kind: VertexKind, p: Vec3, normal: Vec3, beta: Color, pdf_fwd: f64) -> Self {
+        let mut rec = HitRecord::new();
+        rec.p = p;
+        rec.normal = normal;
+        rec.front_face = true;
+        Self {
+            kind,
+            rec,
+            incoming: Vec3::new(0.0, 0.0, 0.0),
+            beta,
+            pdf_fwd,
+            pdf_rev: 0.0,
+            delta: false,
+        }
+    }
+
+    fn p(&self) -> Vec3 {
+        self.rec.p
+    }
+
+    fn is_connectible(&self) -> bool {
+        match &self.kind {
+            VertexKind::Surface(m, _) => !m.is_specular(),
+            _ => true,
+        }
+    }
+
+    // BSDF times the cosine for light arriving from `incoming` and leaving
+    // along `wi`.
+    fn f(&self, wi: &Vec3, wavelength: Option<f64>) -> Color {
+        match &self.kind {
+            VertexKind::Surface(m, _) => {
+                let origin = self.p() - self.incoming;
+                let r_in = Ray::with_wavelength(&origin, &self.incoming, wavelength);
+                m.eval(&r_in, &self.rec, wi).at_wavelength(wavelength)
+            }
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // Converts a solid angle pdf at this vertex into an area pdf at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p() - self.p();
+        let dist_squared = w.norm_squared();
+        if dist_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / dist_squared;
+        if !next.rec.normal.near_zero() {
+            pdf *= f64::abs(next.rec.normal.dot(&(w / dist_squared.sqrt())));
+        }
+        pdf
+    }
+
+    // Area pdf at `next` of sampling it from this vertex, reached from `prev`.
+    fn pdf(
+        &self,
+        scene: &Scene,
+        prev: Option<&Vertex>,
+        next: &Vertex,
+        wavelength: Option<f64>,
+    ) -> f64 {
+        match &self.kind {
+            VertexKind::Light(index) => {
+                let (_, pdf_dir) = scene.lights()[*index].pdf_le(&self.p(), &(next.p() - self.p()));
+                self.convert_density(pdf_dir, next)
+            }
+            VertexKind::Surface(m, _) => {
+                let prev = match prev {
+                    Some(prev) => prev,
+                    None => return 0.0,
+                };
+                let incoming = self.p() - prev.p();
+                let rec = facing(&self.rec, &incoming);
+                let r_in = Ray::with_wavelength(&prev.rec.p, &incoming, wavelength);
+                let pdf = m.pdf(&r_in, &rec, &(next.p() - self.p()));
+                self.convert_density(pdf, next)
+            }
+            VertexKind::Camera(_) => 0.0,
+        }
+    }
+
+    // Area pdf at this emitter of a light subpath starting on it, which also
+    // includes the probability of picking the light.
+    fn pdf_light_origin(&self, scene: &Scene) -> f64 {
+        match light_index(self) {
+            Some(index) => {
+                let (pdf_pos, _) = scene.lights()[index].pdf_le(&self.p(), &self.rec.normal);
+                pdf_pos / scene.lights().len() as f64
+            }
+            None => 0.0,
+        }
+    }
+
+    // Area pdf at `next` of light leaving this emitter towards it.
+    fn pdf_light(&self, scene: &Scene, next: &Vertex) -> f64 {
+        match light_index(self) {
+            Some(index) => {
+                let (_, pdf_dir) = scene.lights()[index].pdf_le(&self.p(), &(next.p() - self.p()));
+                self.convert_density(pdf_dir, next)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+// Index of the light a vertex lies on.
+fn light_index(v: &Vertex) -> Option<usize> {
+    match &v.kind {
+        VertexKind::Light(index) => Some(*index),
+        VertexKind::Surface(_, light) => *light,
+        VertexKind::Camera(_) => None,
+    }
+}
+
+// Copy of `rec` oriented for a ray arriving along `incoming`.
+fn facing(rec: &HitRecord, incoming: &Vec3) -> HitRecord {
+    let outward = rec.outward_normal();
+    let mut rec = *rec;
+    rec.front_face = incoming.dot(&outward) < 0.0;
+    rec.normal = if rec.front_face { outward } else { -outward };
+    rec
+}
+
+// Radiance along the camera ray `r`, same interface as the path tracer.
+pub fn radiance(
+    r: &Ray,
+    scene: &Scene,
+    max_depth: i32,
+    sampler: &mut dyn Sampler,
+    aovs: &mut PathAovs,
+) -> Color {
+    let wavelength = r.wavelength;
+    let max_vertices = max_depth as usize + 1;
+    let mut color = Color::new(0.0, 0.0, 0.0);
+
+    let mut camera_path = vec![Vertex::new(
+        VertexKind::Camera(None),
+        *r.origin(),
+        Vec3::new(0.0, 0.0, 0.0),
+        Color::new(1.0, 1.0, 1.0),
+        1.0,
+    )];
+    // The camera ray isn't BSDF sampled, the environment it sees is only
+    // found this way.
+    let escaped = random_walk(
+        scene,
+        *r.direction(),
+        wavelength,
+        0.0,
+        max_vertices,
+        &mut camera_path,
+        sampler,
+    );
+    if let VertexKind::Camera(first_hit) = &mut camera_path[0].kind {
+        aovs.first_hit = first_hit.take();
+    }
+    let light_path = light_subpath(scene, wavelength, max_vertices, sampler);
+
+    // Camera rays leaving the scene pick up the environment.
+    if let Some((direction, pdf, beta)) = escaped {
+        let origin = camera_path[camera_path.len() - 1].p();
+        let r = Ray::with_wavelength(&origin, &direction, wavelength);
+        background_by_light(&r, scene, pdf, |light, le| {
+            let c = beta * le.at_wavelength(wavelength);
+            color += c;
+            credit(&mut aovs.groups, scene, light, c);
+        });
+    }
+
+    // Light sampling doesn't need the light subpath, it always runs.
+    for t in 2..=camera_path.len() {
+        for s in 0..=usize::max(light_path.len(), 1) {
+            if s + t > max_depth as usize + 2 {
+                break;
+            }
+            let (c, light) = connect(scene, &light_path, &camera_path, s, t, wavelength, sampler);
+            color += c;
+            credit(&mut aovs.groups, scene, light, c);
+        }
+    }
+
+    color
+}
+
+// Starts a light subpath on a light picked uniformly, like light sampling does.
+fn light_subpath(
+    scene: &Scene,
+    wavelength: Option<f64>,
+    max_vertices: usize,
+    sampler: &mut dyn Sampler,
+) -> Vec<Vertex> {
+    let mut path = Vec::new();
+    if scene.lights().is_empty() {
+        return path;
+    }
+    let light_count = scene.lights().len();
+    let index = usize::min(
+        (sampler.get_1d() * light_count as f64) as usize,
+        light_count - 1,
+    );
+    let es = match scene.lights()[index].sample_le() {
+        Some(es) if es.pdf_pos > 0.0 && es.pdf_dir > 0.0 && !es.le.is_black() => es,
+        _ => return path,
+    };
+
+    let pdf_origin = es.pdf_pos / light_count as f64;
+    let le = es.le.at_wavelength(wavelength);
+    path.push(Vertex::new(
+        VertexKind::Light(index),
+        es.origin,
+        es.normal,
+        le,
+        pdf_origin,
+    ));
+
+    // The light vertex carries the throughput of the emitted ray.
+    let cosine = if es.normal.near_zero() {
+        1.0
+    } else {
+        f64::abs(es.normal.dot(&es.direction))
+    };
+    path[0].beta = le * (cosine / (pdf_origin * es.pdf_dir));
+    random_walk(
+        scene,
+        es.direction,
+        wavelength,
+        es.pdf_dir,
+        max_vertices,
+        &mut path,
+        sampler,
+    );
+    path
+}
+
+// Extends `path` by sampling the BSDFs, starting from its last vertex in
+// `direction` sampled with solid angle pdf `pdf`. The beta of the last vertex
+// holds the throughput of the first ray. Returns the direction, pdf and
+// throughput of the last ray when it leaves the scene.
+fn random_walk(
+    scene: &Scene,
+    mut direction: Vec3,
+    wavelength: Option<f64>,
+    mut pdf: f64,
+    max_vertices: usize,
+    path: &mut Vec<Vertex>,
+    sampler: &mut dyn Sampler,
+) -> Option<(Vec3, f64, Color)> {
+    let mut beta = path[path.len() - 1].beta;
+    let mut depth = 0;
+    while path.len() < max_vertices {
+        let origin = path[path.len() - 1].p();
+        let r = Ray::with_wavelength(&origin, &direction, wavelength);
+        let prev = path.len() - 1;
+        let mut rec = HitRecord::new();
+        let hit = match &mut path[prev].kind {
+            VertexKind::Camera(first_hit) => hit_first(&r, scene, &mut rec, first_hit),
+            _ => {
+                let (is_hit, hit_material) = scene.world.hit(&r, 0.001, f64::INFINITY, &mut rec);
+                is_hit.then_some(hit_material)
+            }
+        };
+        let hit_material = match hit {
+            Some(hit_material) => hit_material,
+            None => return Some((direction, pdf, beta)),
+        };
+
+        let m = MaterialType::resolve(&hit_material, &r, &rec);
+        let light = scene.lights().iter().position(|l| l.owns(&hit_material));
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface(Rc::clone(&m), light),
+            rec,
+            incoming: direction,
+            beta,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+        };
+        vertex.pdf_fwd = path[prev].convert_density(pdf, &vertex);
+        path.push(vertex);
+        if path.len() >= max_vertices {
+            break;
+        }
+
+        let mut attenuation = Color::new(0.0, 0.0, 0.0);
+        let (is_scattered, scattered) = m.scatter(&r, &rec, &mut attenuation);
+        if !is_scattered {
+            break;
+        }
+
+        // Specular vertices get zero pdfs, the weights skip them.
+        let current = path.len() - 1;
+        let pdf_rev;
+        if m.is_specular() {
+            path[current].delta = true;
+            pdf = 0.0;
+            pdf_rev = 0.0;
+        } else {
+            pdf = m.pdf(&r, &rec, &scattered);
+            let reversed = -scattered;
+            let end = rec.p + scattered;
+            let r_rev = Ray::with_wavelength(&end, &reversed, wavelength);
+            pdf_rev = m.pdf(&r_rev, &facing(&rec, &reversed), &-direction);
+        }
+        path[prev].pdf_rev = path[current].convert_density(pdf_rev, &path[prev]);
+
+        beta *= attenuation.at_wavelength(wavelength);
+        if beta.is_black() || !russian_roulette(&mut beta, depth, sampler) {
+            break;
+        }
+        direction = scattered;
+        depth += 1;
+    }
+    None
+}
+
+// Contribution of the strategy using the first `s` light and `t` camera
+// vertices, and the light it comes from.
+fn connect(
+    scene: &Scene,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+    wavelength: Option<f64>,
+    sampler: &mut dyn Sampler,
+) -> (Color, Option<usize>) {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let pt = &camera_path[t - 1];
+
+    if s == 0 {
+        // The camera subpath found an emitter by itself.
+        let m = match &pt.kind {
+            VertexKind::Surface(m, _) => m,
+            _ => return (black, None),
+        };
+        let le = m.emitted(&pt.rec).at_wavelength(wavelength);
+        if le.is_black() {
+            return (black, None);
+        }
+        let weight = mis_weight(scene, light_path, camera_path, None, s, t, wavelength);
+        return (pt.beta * le * weight, light_index(pt));
+    }
+
+    if !pt.is_connectible() {
+        return (black, None);
+    }
+
+    if s == 1 {
+        return connect_light(scene, light_path, camera_path, t, wavelength, sampler);
+    }
+
+    let qs = &light_path[s - 1];
+    if !qs.is_connectible() {
+        return (black, None);
+    }
+    let d = qs.p() - pt.p();
+    let dist = d.norm();
+    let l = qs.beta * qs.f(&-d, wavelength) * pt.f(&d, wavelength) * pt.beta / (dist * dist);
+    if l.is_black() || occluded(scene, &pt.p(), &(d / dist), dist) {
+        return (black, None);
+    }
+    let weight = mis_weight(scene, light_path, camera_path, None, s, t, wavelength);
+    (l * weight, light_index(&light_path[0]))
+}
+
+// Strategy with a single light vertex, sampled from the camera vertex.
+fn connect_light(
+    scene: &Scene,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    t: usize,
+    wavelength: Option<f64>,
+    sampler: &mut dyn Sampler,
+) -> (Color, Option<usize>) {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let pt = &camera_path[t - 1];
+    let light_count = scene.lights().len();
+    if light_count == 0 {
+        return (black, None);
+    }
+    let index = usize::min(
+        (sampler.get_1d() * light_count as f64) as usize,
+        light_count - 1,
+    );
+    let light = &scene.lights()[index];
+
+    let ls = match light.sample_li(&pt.p()) {
+        Some(ls) if ls.pdf > 0.0 && !ls.li.is_black() => ls,
+        _ => return (black, None),
+    };
+    let light_pdf = ls.pdf / light_count as f64;
+    let f = pt.f(&ls.wi, wavelength);
+    if f.is_black() || occluded(scene, &pt.p(), &ls.wi, ls.dist) {
+        return (black, None);
+    }
+    let l = pt.beta * f * ls.li.at_wavelength(wavelength) / light_pdf;
+
+    // Lights at infinity only compete with camera rays escaping the scene.
+    if ls.dist == f64::INFINITY {
+        if light.is_delta() {
+            return (l, Some(index));
+        }
+        let m = match &pt.kind {
+            VertexKind::Surface(m, _) => m,
+            _ => return (black, None),
+        };
+        let origin = pt.p() - pt.incoming;
+        let r_in = Ray::with_wavelength(&origin, &pt.incoming, wavelength);
+        let weight = power_heuristic(light_pdf, m.pdf(&r_in, &pt.rec, &ls.wi));
+        return (l * weight, Some(index));
+    }
+
+    let target = pt.p() + ls.wi * ls.dist;
+    let normal = match light {
+        LightType::Area(a) => a.normal_at(&target),
+        LightType::Triangle(t) => t.normal_at(&target),
+        _ => Vec3::new(0.0, 0.0, 0.0),
+    };
+    let mut sampled = Vertex::new(VertexKind::Light(index), target, normal, l, 0.0);
+    sampled.pdf_fwd = sampled.pdf_light_origin(scene);
+    let weight = mis_weight(
+        scene,
+        light_path,
+        camera_path,
+        Some(sampled),
+        1,
+        t,
+        wavelength,
+    );
+    (l * weight, Some(index))
+}
+
+fn occluded(scene: &Scene, p: &Vec3, direction: &Vec3, dist: f64) -> bool {
+    let mut rec = HitRecord::new();
+    let r = Ray::new(p, direction);
+    let (is_hit, _) = scene.world.hit(&r, 0.001, dist - 0.001, &mut rec);
+    is_hit
+}
+
+// Balance heuristic weight of a strategy, from the ratios of the pdfs of the
+// other strategies that could have produced the same path.
+fn mis_weight(
+    scene: &Scene,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    sampled: Option<Vertex>,
+    s: usize,
+    t: usize,
+    wavelength: Option<f64>,
+) -> f64 {
+    if s + t == 2 {
+        return 1.0;
+    }
+    let mut light: Vec<Vertex> = match sampled {
+        Some(sampled) => vec![sampled],
+        None => light_path[..s].to_vec(),
+    };
+    let mut camera: Vec<Vertex> = camera_path[..t].to_vec();
+    let pt = t - 1;
+
+    // Update the pdfs around the connection, which the subpaths couldn't know.
+    if s == 0 {
+        // Emitters that aren't scene lights can only be found this way.
+        if light_index(&camera[pt]).is_none() {
+            return 1.0;
+        }
+        camera[pt].pdf_rev = camera[pt].pdf_light_origin(scene);
+        camera[pt - 1].pdf_rev = camera[pt].pdf_light(scene, &camera[pt - 1]);
+    } else {
+        let qs = s - 1;
+        let q_prev = if qs > 0 { Some(&light[qs - 1]) } else { None };
+        camera[pt].pdf_rev = light[qs].pdf(scene, q_prev, &camera[pt], wavelength);
+        camera[pt - 1].pdf_rev =
+            camera[pt].pdf(scene, Some(&light[qs]), &camera[pt - 1], wavelength);
+        let rev = camera[pt].pdf(scene, Some(&camera[pt - 1]), &light[qs], wavelength);
+        light[qs].pdf_rev = rev;
+        if qs > 0 {
+            light[qs - 1].pdf_rev =
+                light[qs].pdf(scene, Some(&camera[pt]), &light[qs - 1], wavelength);
+        }
+    }
+    camera[pt].delta = false;
+    if s > 0 {
+        light[s - 1].delta = false;
+    }
+
+    // Delta distributions have zero pdfs, they cancel out in the ratios.
+    let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+    let mut sum = 0.0;
+
+    // Strategies with fewer camera vertices, down to two of them.
+    let mut ri = 1.0;
+    for i in (2..t).rev() {
+        ri *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+        if !camera[i].delta && !camera[i - 1].delta {
+            sum += ri;
+        }
+    }
+
+    // Strategies with fewer light vertices.
+    let mut ri = 1.0;
+    for i in (0..s).rev() {
+        ri *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+        let delta_light = if i > 0 {
+            light[i - 1].delta
+        } else {
+            light_index(&light[0]).is_none_or(|l| scene.lights()[l].is_delta())
+        };
+        if !light[i].delta && !delta_light {
+            sum += ri;
+        }
+    }
+
+    1.0 / (1.0 + sum)
+}
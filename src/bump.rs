@@ -0,0 +1,102 @@
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::material::{LightReflection, MaterialType};
+use crate::ray::Ray;
+use crate::texture::ImageTexture;
+use crate::vec::Vec3;
+use std::rc::Rc;
+
+pub enum SurfaceMap {
+    // Tangent space normals encoded as colors, x along the tangent, y along
+    // the bitangent and z along the normal.
+    Normal(Rc<ImageTexture>),
+    // Heights in the texture's luminance, the normal follows its slopes.
+    Height(Rc<ImageTexture>),
+}
+
+// Wraps any material and replaces the geometric normal by one perturbed with a
+// normal or height map before handing the hit to it.
+pub struct Bumped {
+    pub base: Rc<MaterialType>,
+    pub map: SurfaceMap,
+    // Blends between the geometric normal (0) and the mapped one (1) for
+    // normal maps, scales the slopes of height maps.
+    pub strength: f64,
+}
+
+impl Bumped {
+    pub fn normal_map(base: Rc<MaterialType>, map: Rc<ImageTexture>) -> Self {
+        Self {
+            base,
+            map: SurfaceMap::Normal(map),
+            strength: 1.0,
+        }
+    }
+
+    pub fn height_map(base: Rc<MaterialType>, map: Rc<ImageTexture>) -> Self {
+        Self {
+            base,
+            map: SurfaceMap::Height(map),
+            strength: 1.0,
+        }
+    }
+
+    pub fn with_strength(mut self, strength: f64) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        let n = rec.outward_normal();
+        let (t, b) = (rec.tangent, rec.bitangent);
+
+        let mapped = match &self.map {
+            SurfaceMap::Normal(texture) => {
+                let c = texture.value(rec.u, rec.v);
+                let local = Vec3::new(2.0 * c.r() - 1.0, 2.0 * c.g() - 1.0, 2.0 * c.b() - 1.0);
+                let mapped = t * local.x() + b * local.y() + n * local.z();
+                n * (1.0 - self.strength) + mapped * self.strength
+            }
+            SurfaceMap::Height(texture) => {
+                // Forward differences over one texel.
+                let du = 1.0 / texture.width as f64;
+                let dv = 1.0 / texture.height as f64;
+                let h = texture.value(rec.u, rec.v).luminance();
+                let dhdu = (texture.value(rec.u + du, rec.v).luminance() - h) / du;
+                let dhdv = (texture.value(rec.u, rec.v + dv).luminance() - h) / dv;
+                n - (t * dhdu + b * dhdv) * self.strength
+            }
+        };
+        if mapped.near_zero() || mapped.dot(&n) <= 0.0 {
+            return *rec;
+        }
+
+        let mut perturbed = *rec;
+        let mapped = Vec3::unit_vector(&mapped);
+        perturbed.normal = if rec.front_face { mapped } else { -mapped };
+        perturbed.set_tangent_frame(&mapped, &t, &b);
+        perturbed
+    }
+}
+
+impl LightReflection for Bumped {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        self.base.scatter(r_in, &self.perturb(rec), attenuation)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        self.base.eval(r_in, &self.perturb(rec), scattered)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        self.base.pdf(r_in, &self.perturb(rec), scattered)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.base.emitted(&self.perturb(rec))
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+}
@@ -1,7 +1,8 @@
+use crate::spectrum::rgb_to_spectrum;
 use crate::utils::clamp;
 use std::{
     fmt::Display,
-    ops::{Add, AddAssign, Mul, MulAssign},
+    ops::{Add, AddAssign, Div, Mul, MulAssign},
 };
 
 #[derive(PartialEq, Clone, Copy)]
@@ -28,6 +29,35 @@ impl Color {
         self.b
     }
 
+    // Relative luminance of linear sRGB values.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn exp(&self) -> Self {
+        Self::new(f64::exp(self.r), f64::exp(self.g), f64::exp(self.b))
+    }
+
+    pub fn max_component(&self) -> f64 {
+        f64::max(self.r, f64::max(self.g, self.b))
+    }
+
+    // In spectral mode, the grey color holding the value of this color's
+    // spectrum at the given wavelength.
+    pub fn at_wavelength(&self, wavelength: Option<f64>) -> Self {
+        match wavelength {
+            Some(lambda) => {
+                let value = rgb_to_spectrum(self, lambda);
+                Self::new(value, value, value)
+            }
+            None => *self,
+        }
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r == 0.0 && self.g == 0.0 && self.b == 0.0
+    }
+
     pub fn write_color(&self, samples_per_pixel: u32) {
         let scale = 1.0 / samples_per_pixel as f64;
         println!(
@@ -105,6 +135,18 @@ impl MulAssign<Self> for Color {
     }
 }
 
+impl Div<f64> for Color {
+    type Output = Self;
+
+    fn div(self, rhs: f64) -> Self::Output {
+        Self {
+            r: self.r / rhs,
+            g: self.g / rhs,
+            b: self.b / rhs,
+        }
+    }
+}
+
 impl Display for Color {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
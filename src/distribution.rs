@@ -0,0 +1,115 @@
+use crate::utils::random_number;
+
+// Piecewise constant 1D distribution over [0, 1) built from unnormalized weights.
+pub struct Distribution1D {
+    pub func: Vec<f64>,
+    cdf: Vec<f64>,
+    pub integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if integral == 0.0 {
+                i as f64 / n as f64
+            } else {
+                *c / integral
+            };
+        }
+
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    // Returns the sampled position in [0, 1), its pdf and the bucket it falls in.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let offset = self.find_interval(u);
+
+        let mut du = u - self.cdf[offset];
+        if self.cdf[offset + 1] - self.cdf[offset] > 0.0 {
+            du /= self.cdf[offset + 1] - self.cdf[offset];
+        }
+
+        let pdf = if self.integral > 0.0 {
+            self.func[offset] / self.integral
+        } else {
+            0.0
+        };
+
+        ((offset as f64 + du) / self.count() as f64, pdf, offset)
+    }
+
+    // Returns the sampled bucket and the probability of picking it.
+    pub fn sample_discrete(&self) -> (usize, f64) {
+        let offset = self.find_interval(random_number());
+        (offset, self.discrete_pdf(offset))
+    }
+
+    pub fn discrete_pdf(&self, index: usize) -> f64 {
+        if self.integral == 0.0 {
+            return 0.0;
+        }
+        self.func[index] / (self.integral * self.count() as f64)
+    }
+
+    fn find_interval(&self, u: f64) -> usize {
+        let index = self.cdf.partition_point(|&c| c <= u);
+        index.saturating_sub(1).min(self.count() - 1)
+    }
+}
+
+// Piecewise constant 2D distribution, sampled as a marginal over rows and a
+// conditional distribution inside the chosen row.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // `func` is laid out row by row, `width` values per row.
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..height)
+            .map(|v| Distribution1D::new(func[v * width..(v + 1) * width].to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.integral).collect());
+
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    // Returns the sampled (u, v) in [0, 1)^2 and its pdf.
+    pub fn sample_continuous(&self, u0: f64, u1: f64) -> (f64, f64, f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u0);
+        (u, v, pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = &self.conditional[0];
+        let iu = usize::min((u * row.count() as f64) as usize, row.count() - 1);
+        let iv = usize::min(
+            (v * self.marginal.count() as f64) as usize,
+            self.marginal.count() - 1,
+        );
+        if self.marginal.integral == 0.0 {
+            return 0.0;
+        }
+        self.conditional[iv].func[iu] / self.marginal.integral
+    }
+}
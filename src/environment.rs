@@ -0,0 +1,268 @@
+use crate::color::Color;
+use crate::distribution::Distribution2D;
+use crate::utils::{degrees_to_radian, random_number};
+use crate::vec::Vec3;
+use std::f64::consts::PI;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+// Radiance arriving from infinitely far away for rays leaving the scene.
+pub trait Environment {
+    fn radiance(&self, dir: &Vec3) -> Color;
+
+    // Importance samples a direction towards the environment, returning it
+    // with its solid angle pdf. Environments that can't be sampled return None.
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        None
+    }
+
+    fn pdf(&self, _dir: &Vec3) -> f64 {
+        0.0
+    }
+}
+
+// Blend between two colors from the bottom to the top of the sky.
+pub struct Gradient {
+    pub bottom: Color,
+    pub top: Color,
+}
+
+impl Gradient {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self::new(Color::new(1.0, 1.0, 1.0), Color::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Environment for Gradient {
+    fn radiance(&self, dir: &Vec3) -> Color {
+        let unit_direction = Vec3::unit_vector(dir);
+        let t = 0.5 * (unit_direction.y() + 1.0);
+        self.bottom * (1.0 - t) + self.top * t
+    }
+}
+
+// Equirectangular HDR image wrapped around the scene. The map can be rotated
+// around the vertical axis and scaled, and is importance sampled by luminance.
+pub struct EnvironmentMap {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+    pub intensity: f64,
+    rotation: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        let mut func = Vec::with_capacity(width * height);
+        for j in 0..height {
+            // Rows near the poles cover less solid angle.
+            let sin_theta = f64::sin(PI * (j as f64 + 0.5) / height as f64);
+            for i in 0..width {
+                func.push(pixels[j * width + i].luminance() * sin_theta);
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            intensity: 1.0,
+            rotation: 0.0,
+            distribution: Distribution2D::new(&func, width, height),
+        }
+    }
+
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let (width, height, pixels) = read_hdr(&fs::read(path)?)?;
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    // Rotation around the y axis, in degrees.
+    pub fn with_rotation(mut self, degrees: f64) -> Self {
+        self.rotation = degrees_to_radian(degrees);
+        self
+    }
+
+    fn rotate_y(&self, dir: &Vec3, angle: f64) -> Vec3 {
+        let (sin, cos) = f64::sin_cos(angle);
+        Vec3::new(
+            cos * dir.x() + sin * dir.z(),
+            dir.y(),
+            -sin * dir.x() + cos * dir.z(),
+        )
+    }
+
+    // Maps a world direction to image coordinates in [0, 1)^2, v = 0 is the zenith.
+    fn direction_to_uv(&self, dir: &Vec3) -> (f64, f64) {
+        let d = Vec3::unit_vector(&self.rotate_y(dir, -self.rotation));
+        let phi = f64::atan2(d.x(), -d.z());
+        let theta = f64::acos(d.y().clamp(-1.0, 1.0));
+        let u = (phi + PI) / (2.0 * PI);
+        (u.rem_euclid(1.0), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let phi = u * 2.0 * PI - PI;
+        let theta = v * PI;
+        let sin_theta = f64::sin(theta);
+        let d = Vec3::new(
+            sin_theta * f64::sin(phi),
+            f64::cos(theta),
+            -sin_theta * f64::cos(phi),
+        );
+        self.rotate_y(&d, self.rotation)
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, dir: &Vec3) -> Color {
+        let (u, v) = self.direction_to_uv(dir);
+        let i = usize::min((u * self.width as f64) as usize, self.width - 1);
+        let j = usize::min((v * self.height as f64) as usize, self.height - 1);
+        self.pixels[j * self.width + i] * self.intensity
+    }
+
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        let (u, v, map_pdf) = self
+            .distribution
+            .sample_continuous(random_number(), random_number());
+        if map_pdf == 0.0 {
+            return None;
+        }
+
+        let sin_theta = f64::sin(v * PI);
+        if sin_theta == 0.0 {
+            return None;
+        }
+
+        // Change of variables from the image to the sphere of directions.
+        let pdf = map_pdf / (2.0 * PI * PI * sin_theta);
+        Some((self.uv_to_direction(u, v), pdf))
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(dir);
+        let sin_theta = f64::sin(v * PI);
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+// Decodes a Radiance RGBE (.hdr) image, flat or new-style run length encoded.
+pub fn read_hdr(data: &[u8]) -> std::io::Result<(usize, usize, Vec<Color>)> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+    let mut pos = 0;
+    let next_line = |pos: &mut usize| -> Option<String> {
+        let start = *pos;
+        let end = start + data[start..].iter().position(|&b| b == b'\n')?;
+        *pos = end + 1;
+        Some(String::from_utf8_lossy(&data[start..end]).into_owned())
+    };
+
+    let magic = next_line(&mut pos).ok_or_else(|| invalid("empty hdr file"))?;
+    if !magic.starts_with("#?") {
+        return Err(invalid("missing radiance header"));
+    }
+
+    loop {
+        let line = next_line(&mut pos).ok_or_else(|| invalid("truncated hdr header"))?;
+        if line.trim().is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line.trim() != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid("unsupported hdr pixel format"));
+        }
+    }
+
+    let resolution = next_line(&mut pos).ok_or_else(|| invalid("missing hdr resolution"))?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    if fields.len() != 4 || fields[0] != "-Y" || fields[2] != "+X" {
+        return Err(invalid("unsupported hdr orientation"));
+    }
+    let height: usize = fields[1].parse().map_err(|_| invalid("bad hdr height"))?;
+    let width: usize = fields[3].parse().map_err(|_| invalid("bad hdr width"))?;
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![0u8; width * 4];
+
+    for _ in 0..height {
+        let rle = (8..32768).contains(&width)
+            && data.len() >= pos + 4
+            && data[pos] == 2
+            && data[pos + 1] == 2
+            && data[pos + 2] & 0x80 == 0;
+
+        if rle {
+            if ((data[pos + 2] as usize) << 8 | data[pos + 3] as usize) != width {
+                return Err(invalid("hdr scanline width mismatch"));
+            }
+            pos += 4;
+
+            // Each channel of the scanline is stored separately as runs.
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = *data.get(pos).ok_or_else(|| invalid("truncated hdr data"))?;
+                    pos += 1;
+                    if count > 128 {
+                        let run = (count - 128) as usize;
+                        let value = *data.get(pos).ok_or_else(|| invalid("truncated hdr data"))?;
+                        pos += 1;
+                        if x + run > width {
+                            return Err(invalid("hdr run overflows scanline"));
+                        }
+                        for _ in 0..run {
+                            scanline[x * 4 + channel] = value;
+                            x += 1;
+                        }
+                    } else {
+                        let run = count as usize;
+                        if run == 0 || x + run > width || pos + run > data.len() {
+                            return Err(invalid("bad hdr literal run"));
+                        }
+                        for i in 0..run {
+                            scanline[x * 4 + channel] = data[pos + i];
+                            x += 1;
+                        }
+                        pos += run;
+                    }
+                }
+            }
+        } else {
+            if pos + width * 4 > data.len() {
+                return Err(invalid("truncated hdr data"));
+            }
+            scanline.copy_from_slice(&data[pos..pos + width * 4]);
+            pos += width * 4;
+        }
+
+        for rgbe in scanline.chunks(4) {
+            pixels.push(rgbe_to_color(rgbe));
+        }
+    }
+
+    Ok((width, height, pixels))
+}
+
+fn rgbe_to_color(rgbe: &[u8]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let f = f64::powi(2.0, rgbe[3] as i32 - 136);
+    Color::new(rgbe[0] as f64 * f, rgbe[1] as f64 * f, rgbe[2] as f64 * f)
+}
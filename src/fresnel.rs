@@ -0,0 +1,191 @@
+use crate::color::Color;
+use crate::spectrum::spectrum_to_rgb;
+use num::complex::Complex64;
+use std::f64::consts::PI;
+
+// Unpolarized Fresnel reflectance at a smooth dielectric interface. `eta` is
+// the ratio of the index of refraction on the transmitted side over the
+// incident side, a negative cosine means the ray arrives from the inside.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let mut cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let mut eta = eta;
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+    }
+
+    let sin2_theta_i = 1.0 - cos_theta_i * cos_theta_i;
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        // Total internal reflection.
+        return 1.0;
+    }
+    let cos_theta_t = f64::sqrt(1.0 - sin2_theta_t);
+
+    let r_parl = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+// Fresnel reflectance of a conductor with complex index of refraction eta + i k.
+pub fn fresnel_complex(cos_theta_i: f64, eta: Complex64) -> f64 {
+    let cos_theta_i = Complex64::new(cos_theta_i.clamp(0.0, 1.0), 0.0);
+    let sin2_theta_i = Complex64::new(1.0, 0.0) - cos_theta_i * cos_theta_i;
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    let cos_theta_t = (Complex64::new(1.0, 0.0) - sin2_theta_t).sqrt();
+
+    let r_parl = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parl.norm_sqr() + r_perp.norm_sqr()) / 2.0
+}
+
+pub fn fresnel_conductor(cos_theta_i: f64, eta: &Color, k: &Color) -> Color {
+    Color::new(
+        fresnel_complex(cos_theta_i, Complex64::new(eta.r(), k.r())),
+        fresnel_complex(cos_theta_i, Complex64::new(eta.g(), k.g())),
+        fresnel_complex(cos_theta_i, Complex64::new(eta.b(), k.b())),
+    )
+}
+
+// (1 - cos)^5 term of Schlick's approximation.
+pub fn schlick_weight(cos_theta: f64) -> f64 {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0);
+    m * m * m * m * m
+}
+
+pub fn fresnel_schlick(f0: &Color, cos_theta: f64) -> Color {
+    let w = schlick_weight(cos_theta);
+    *f0 * (1.0 - w) + Color::new(w, w, w)
+}
+
+// Thin transparent film coating a surface, thickness in nanometers. Light
+// reflected at its two interfaces interferes, tinting the reflection with
+// colors depending on the viewing angle.
+#[derive(Clone, Copy)]
+pub struct ThinFilm {
+    pub thickness: f64,
+    pub ior: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, ior: f64) -> Self {
+        Self { thickness, ior }
+    }
+
+    // Reflectance at wavelength `lambda` (nm) for light arriving through a
+    // medium of index `eta_i` onto the film over a substrate of complex index
+    // `substrate`, summing all the reflections inside the film (Airy).
+    pub fn reflectance(
+        &self,
+        cos_theta_i: f64,
+        eta_i: f64,
+        substrate: Complex64,
+        lambda: f64,
+    ) -> f64 {
+        let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+        let n1 = Complex64::new(eta_i, 0.0);
+        let n2 = Complex64::new(self.ior, 0.0);
+        let n3 = substrate;
+
+        // Snell's law, complex past the critical angle or in a conductor.
+        let sin_theta_i = Complex64::new(f64::sqrt(1.0 - cos_theta_i * cos_theta_i), 0.0);
+        let cos_in = |n: Complex64| {
+            let sin = n1 * sin_theta_i / n;
+            (Complex64::new(1.0, 0.0) - sin * sin).sqrt()
+        };
+        let c1 = Complex64::new(cos_theta_i, 0.0);
+        let c2 = cos_in(n2);
+        let c3 = cos_in(n3);
+
+        let r_perp = |na: Complex64, ca: Complex64, nb: Complex64, cb: Complex64| {
+            (na * ca - nb * cb) / (na * ca + nb * cb)
+        };
+        let r_parl = |na: Complex64, ca: Complex64, nb: Complex64, cb: Complex64| {
+            (nb * ca - na * cb) / (nb * ca + na * cb)
+        };
+
+        // Phase difference of one round trip through the film.
+        let phase = n2 * c2 * (4.0 * PI * self.thickness / lambda);
+        let shift = (Complex64::new(0.0, 1.0) * phase).exp();
+        let airy = |r12: Complex64, r23: Complex64| {
+            ((r12 + r23 * shift) / (Complex64::new(1.0, 0.0) + r12 * r23 * shift)).norm_sqr()
+        };
+
+        (airy(r_perp(n1, c1, n2, c2), r_perp(n2, c2, n3, c3))
+            + airy(r_parl(n1, c1, n2, c2), r_parl(n2, c2, n3, c3)))
+            / 2.0
+    }
+}
+
+// Reflectance of a film coated interface between a medium of index `eta_i`
+// and a substrate of complex index eta + i k, given per color channel. The
+// color integrated over the visible spectrum is tabulated against the angle
+// of incidence, spectral paths evaluate their own wavelength exactly.
+pub struct FilmFresnel {
+    pub film: ThinFilm,
+    eta_i: f64,
+    eta: Color,
+    k: Color,
+    table: Vec<Color>,
+}
+
+impl FilmFresnel {
+    pub fn new(film: ThinFilm, eta_i: f64, eta: Color, k: Color) -> Self {
+        let mut fresnel = Self {
+            film,
+            eta_i,
+            eta,
+            k,
+            table: Vec::new(),
+        };
+        fresnel.table = (0..FILM_TABLE_SIZE)
+            .map(|i| {
+                let cos_theta = i as f64 / (FILM_TABLE_SIZE - 1) as f64;
+                spectrum_to_rgb(|lambda| fresnel.at_wavelength(cos_theta, lambda))
+            })
+            .collect();
+        fresnel
+    }
+
+    fn at_wavelength(&self, cos_theta_i: f64, lambda: f64) -> f64 {
+        let substrate = Complex64::new(
+            channel_at_wavelength(&self.eta, lambda),
+            channel_at_wavelength(&self.k, lambda),
+        );
+        self.film
+            .reflectance(cos_theta_i, self.eta_i, substrate, lambda)
+    }
+
+    pub fn evaluate(&self, cos_theta_i: f64, wavelength: Option<f64>) -> Color {
+        if let Some(lambda) = wavelength {
+            let r = self.at_wavelength(cos_theta_i, lambda);
+            return Color::new(r, r, r);
+        }
+        let x = cos_theta_i.clamp(0.0, 1.0) * (FILM_TABLE_SIZE - 1) as f64;
+        let i = usize::min(x as usize, FILM_TABLE_SIZE - 2);
+        let t = x - i as f64;
+        self.table[i] * (1.0 - t) + self.table[i + 1] * t
+    }
+}
+
+const FILM_TABLE_SIZE: usize = 128;
+
+// Wavelengths the red, green and blue channels of a color stand for, in nm.
+const CHANNEL_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+
+// Piecewise linear reading of an rgb quantity, such as an index of
+// refraction, at a wavelength.
+pub fn channel_at_wavelength(c: &Color, lambda: f64) -> f64 {
+    let [red, green, blue] = CHANNEL_WAVELENGTHS;
+    if lambda >= red {
+        c.r()
+    } else if lambda >= green {
+        let t = (lambda - green) / (red - green);
+        c.g() * (1.0 - t) + c.r() * t
+    } else if lambda >= blue {
+        let t = (lambda - blue) / (green - blue);
+        c.b() * (1.0 - t) + c.g() * t
+    } else {
+        c.b()
+    }
+}
@@ -1,4 +1,5 @@
 use crate::material::MaterialType;
+use crate::onb::Onb;
 use crate::ray::Ray;
 use crate::vec::Vec3;
 use std::rc::Rc;
@@ -8,14 +9,35 @@ pub trait Hittable {
         -> (bool, Rc<MaterialType>);
 }
 
+#[derive(Clone, Copy)]
 pub struct HitRecord {
     pub p: Vec3,
     pub normal: Vec3,
     pub t: f64,
     pub front_face: bool,
+    // Surface parameterization and the tangent frame following it: `tangent`
+    // points towards increasing u and `bitangent` towards increasing v. Both
+    // are unit length and perpendicular to the outward geometric normal.
+    pub u: f64,
+    pub v: f64,
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
 }
 
 impl HitRecord {
+    pub fn new() -> Self {
+        Self {
+            p: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            t: 0.0,
+            front_face: false,
+            u: 0.0,
+            v: 0.0,
+            tangent: Vec3::new(0.0, 0.0, 0.0),
+            bitangent: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_face = r.direction().dot(outward_normal) < 0.0;
         self.normal = if self.front_face {
@@ -24,4 +46,37 @@ impl HitRecord {
             -*outward_normal
         };
     }
+
+    // Builds the tangent frame from the surface derivative along u. The
+    // bitangent is flipped when needed so it follows `dpdv`.
+    pub fn set_tangent_frame(&mut self, outward_normal: &Vec3, dpdu: &Vec3, dpdv: &Vec3) {
+        let n = Vec3::unit_vector(outward_normal);
+        let t = dpdu - n * n.dot(dpdu);
+        if t.near_zero() {
+            let uvw = Onb::build_from_w(&n);
+            self.tangent = uvw.u;
+            self.bitangent = uvw.v;
+            return;
+        }
+        self.tangent = Vec3::unit_vector(&t);
+        self.bitangent = n.cross(&self.tangent);
+        if self.bitangent.dot(dpdv) < 0.0 {
+            self.bitangent = -self.bitangent;
+        }
+    }
+
+    // Outward facing normal, whichever side the ray came from.
+    pub fn outward_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
+}
+
+impl Default for HitRecord {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -3,13 +3,18 @@ use crate::hittable::{HitRecord, Hittable};
 use crate::material::Lambertian;
 use crate::material::MaterialType;
 use crate::ray::Ray;
-use crate::vec::Vec3;
 use std::rc::Rc;
-pub struct HittableList<T> {
+pub struct HittableList<T: ?Sized> {
     pub objects: Vec<Rc<T>>,
 }
 
-impl<T> HittableList<T> {
+impl<T: ?Sized> Default for HittableList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ?Sized> HittableList<T> {
     pub fn new() -> Self {
         Self {
             objects: Vec::new(),
@@ -25,7 +30,33 @@ impl<T> HittableList<T> {
     }
 }
 
-impl<'a, T: Hittable> Hittable for HittableList<T> {
+impl<T: Hittable + ?Sized> HittableList<T> {
+    // Like `hit`, also returning the index of the object hit.
+    pub fn hit_object(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+    ) -> Option<(usize, Rc<MaterialType>)> {
+        let mut temp_rec = HitRecord::new();
+        let mut closest = None;
+        let mut closest_so_far = t_max;
+
+        for (index, object) in self.objects.iter().enumerate() {
+            let (is_hit, m) = object.hit(r, t_min, closest_so_far, &mut temp_rec);
+            if is_hit {
+                closest = Some((index, m));
+                closest_so_far = temp_rec.t;
+                *rec = temp_rec;
+            }
+        }
+
+        closest
+    }
+}
+
+impl<T: Hittable + ?Sized> Hittable for HittableList<T> {
     fn hit(
         &self,
         r: &Ray,
@@ -33,12 +64,7 @@ impl<'a, T: Hittable> Hittable for HittableList<T> {
         t_max: f64,
         rec: &mut HitRecord,
     ) -> (bool, Rc<MaterialType>) {
-        let mut temp_rec = HitRecord {
-            p: Vec3::new(0.0, 0.0, 0.0),
-            normal: Vec3::new(0.0, 0.0, 0.0),
-            t: 0.0,
-            front_face: false,
-        };
+        let mut temp_rec = HitRecord::new();
         let mut hit_anything = false;
         let mut material: Rc<MaterialType> = Rc::new(MaterialType::Lambertian(Lambertian::new(
             Color::new(0.8, 0.8, 0.0),
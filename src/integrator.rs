@@ -0,0 +1,677 @@
+use crate::aov::{FirstHit, PathAovs};
+use crate::bdpt;
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::light::{LightSampling, LightType};
+use crate::material::{LightReflection, MaterialType};
+use crate::onb::Onb;
+use crate::photon::PhotonMap;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::subsurface::Medium;
+use crate::utils::power_heuristic;
+use crate::vec::Vec3;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// Computes the radiance arriving along a camera ray. Integrators draw their
+// own decisions from `sampler`, while materials and lights sample through
+// `random_number`. In spectral mode `r` carries a wavelength and the returned
+// color is grey, holding the radiance at that wavelength.
+pub trait Integrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color;
+
+    // Like `li`, also recording the first surface hit by `r` and adding the
+    // radiance from each light group to `aovs`. Only filled in by the
+    // integrators that support AOVs.
+    fn li_with_aovs(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        _aovs: &mut PathAovs,
+    ) -> Color {
+        self.li(r, scene, sampler)
+    }
+
+    fn supports_aovs(&self) -> bool {
+        false
+    }
+}
+
+// Command line names of the integrators, the first being the default.
+pub const NAMES: [&str; 6] = ["path", "bdpt", "whitted", "normals", "depth", "ao"];
+
+// Integrator selected by its command line name, the photon map only being
+// used by the path tracer.
+pub fn by_name(
+    name: &str,
+    max_depth: i32,
+    caustics: Option<PhotonMap>,
+) -> Option<Box<dyn Integrator>> {
+    let integrator: Box<dyn Integrator> = match name {
+        "path" => {
+            let mut path = PathIntegrator::new(max_depth);
+            if let Some(map) = caustics {
+                path = path.with_caustics(map);
+            }
+            Box::new(path)
+        }
+        "bdpt" => Box::new(BdptIntegrator::new(max_depth)),
+        "whitted" => Box::new(WhittedIntegrator::new(max_depth)),
+        "normals" => Box::new(NormalsIntegrator),
+        "depth" => Box::new(DepthIntegrator::new(10.0)),
+        "ao" => Box::new(AmbientOcclusionIntegrator::new(16, 1.0)),
+        _ => return None,
+    };
+    Some(integrator)
+}
+
+// Paths shorter than this are never terminated by Russian roulette.
+const MIN_BOUNCES: i32 = 3;
+
+// Cap on the scattering events of a single random walk inside a medium.
+const MAX_WALK_STEPS: i32 = 256;
+
+// Unidirectional path tracer with next-event estimation, random walks through
+// media and optionally a caustic photon map.
+pub struct PathIntegrator {
+    pub max_depth: i32,
+    pub caustics: Option<PhotonMap>,
+}
+
+impl PathIntegrator {
+    pub fn new(max_depth: i32) -> Self {
+        Self {
+            max_depth,
+            caustics: None,
+        }
+    }
+
+    pub fn with_caustics(mut self, caustics: PhotonMap) -> Self {
+        self.caustics = Some(caustics);
+        self
+    }
+}
+
+impl Integrator for PathIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        self.li_with_aovs(r, scene, sampler, &mut PathAovs::default())
+    }
+
+    fn li_with_aovs(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        aovs: &mut PathAovs,
+    ) -> Color {
+        let wavelength = r.wavelength;
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut origin = *r.origin();
+        let mut direction = *r.direction();
+
+        // Pdf of the BSDF sample that produced the current ray, zero when it came
+        // from the camera or a specular bounce and can't be light sampled.
+        let mut bsdf_pdf = 0.0;
+
+        // Medium the ray currently travels through, entered by refracting into an
+        // object with a scattering interior.
+        let mut medium: Option<Medium> = None;
+        let mut walk_steps = 0;
+
+        let mut after_diffuse = false;
+        let mut caustic = false;
+
+        // Surface bounces so far, the scattering events of media don't count.
+        let mut depth = 0;
+        while depth < self.max_depth {
+            let r = Ray::with_wavelength(&origin, &direction, wavelength);
+            let mut rec = HitRecord::new();
+
+            let hit = if depth == 0 {
+                hit_first(&r, scene, &mut rec, &mut aovs.first_hit)
+            } else {
+                let (is_hit, hit_material) = scene.world.hit(&r, 0.001, f64::INFINITY, &mut rec);
+                is_hit.then_some(hit_material)
+            };
+            let hit_material = match hit {
+                Some(hit_material) => hit_material,
+                None => {
+                    background_by_light(&r, scene, bsdf_pdf, |light, le| {
+                        let c = throughput * le.at_wavelength(wavelength);
+                        color += c;
+                        credit(&mut aovs.groups, scene, light, c);
+                    });
+                    break;
+                }
+            };
+
+            // Volume scattering events inside a medium don't count as bounces.
+            if let Some(inside) = &medium {
+                let (scatter_distance, weight) =
+                    inside.sample(rec.t * r.direction().norm(), wavelength);
+                throughput *= weight;
+                if let Some(distance) = scatter_distance {
+                    walk_steps += 1;
+                    if walk_steps > MAX_WALK_STEPS
+                        || !russian_roulette(&mut throughput, depth, sampler)
+                    {
+                        break;
+                    }
+                    origin += Vec3::unit_vector(&direction) * distance;
+                    direction = inside.sample_phase();
+                    bsdf_pdf = 0.0;
+                    after_diffuse = false;
+                    caustic = false;
+                    continue;
+                }
+            }
+
+            let m = MaterialType::resolve(&hit_material, &r, &rec);
+            let mut emitted = m.emitted(&rec).at_wavelength(wavelength);
+
+            // Emission reached through BSDF sampling is also reachable through
+            // light sampling at the previous bounce, weight it accordingly.
+            if bsdf_pdf > 0.0 && !emitted.is_black() {
+                let light_pdf =
+                    scene.light_pdf(&hit_material, r.origin(), &Vec3::unit_vector(r.direction()));
+                emitted *= power_heuristic(bsdf_pdf, light_pdf);
+            }
+            let owner = scene.lights().iter().position(|l| l.owns(&hit_material));
+            if caustic && owner.is_some() {
+                emitted = Color::new(0.0, 0.0, 0.0);
+            }
+            color += throughput * emitted;
+            credit(&mut aovs.groups, scene, owner, throughput * emitted);
+
+            let mut attenuation = Color::new(0.0, 0.0, 0.0);
+            let (is_scattered, scatter_direction) = m.scatter(&r, &rec, &mut attenuation);
+            if !is_scattered {
+                break;
+            }
+
+            if m.is_specular() {
+                bsdf_pdf = 0.0;
+                // Photons don't enter media, so they can't stand in for these paths.
+                if m.medium().is_some() {
+                    after_diffuse = false;
+                }
+                caustic = after_diffuse;
+            } else {
+                let (direct, light) = sample_light(&r, &rec, &m, scene, sampler);
+                color += throughput * direct;
+                credit(&mut aovs.groups, scene, light, throughput * direct);
+                bsdf_pdf = m.pdf(&r, &rec, &scatter_direction);
+                if let Some(map) = &self.caustics {
+                    map.estimate(&r, &rec, &m, |light, l| {
+                        let c = throughput * l.at_wavelength(wavelength);
+                        color += c;
+                        credit(&mut aovs.groups, scene, Some(light), c);
+                    });
+                    after_diffuse = true;
+                }
+                caustic = false;
+            }
+
+            throughput *= attenuation.at_wavelength(wavelength);
+
+            if let Some(interior) = m.medium() {
+                let entering = scatter_direction.dot(&rec.outward_normal()) < 0.0;
+                medium = if entering { Some(interior) } else { None };
+                walk_steps = 0;
+            }
+
+            if !russian_roulette(&mut throughput, depth, sampler) {
+                break;
+            }
+
+            origin = rec.p;
+            direction = scatter_direction;
+            depth += 1;
+        }
+
+        color
+    }
+
+    fn supports_aovs(&self) -> bool {
+        true
+    }
+}
+
+// Bidirectional path tracer, see `bdpt`.
+pub struct BdptIntegrator {
+    pub max_depth: i32,
+}
+
+impl BdptIntegrator {
+    pub fn new(max_depth: i32) -> Self {
+        Self { max_depth }
+    }
+}
+
+impl Integrator for BdptIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        self.li_with_aovs(r, scene, sampler, &mut PathAovs::default())
+    }
+
+    fn li_with_aovs(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        aovs: &mut PathAovs,
+    ) -> Color {
+        bdpt::radiance(r, scene, self.max_depth, sampler, aovs)
+    }
+
+    fn supports_aovs(&self) -> bool {
+        true
+    }
+}
+
+// Whitted-style ray tracer: direct light from every light at diffuse and
+// glossy hits, recursion only through specular bounces.
+pub struct WhittedIntegrator {
+    pub max_depth: i32,
+}
+
+impl WhittedIntegrator {
+    pub fn new(max_depth: i32) -> Self {
+        Self { max_depth }
+    }
+
+    // Radiance along `r`, crediting the light groups with it weighted by
+    // `throughput`, the attenuation of the specular bounces leading to `r`.
+    fn trace(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        depth: i32,
+        throughput: Color,
+        aovs: &mut PathAovs,
+    ) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let wavelength = r.wavelength;
+        if depth >= self.max_depth {
+            return black;
+        }
+
+        let mut rec = HitRecord::new();
+        let hit = if depth == 0 {
+            hit_first(r, scene, &mut rec, &mut aovs.first_hit)
+        } else {
+            let (is_hit, hit_material) = scene.world.hit(r, 0.001, f64::INFINITY, &mut rec);
+            is_hit.then_some(hit_material)
+        };
+        let hit_material = match hit {
+            Some(hit_material) => hit_material,
+            None => {
+                let mut color = black;
+                background_by_light(r, scene, 0.0, |light, le| {
+                    let le = le.at_wavelength(wavelength);
+                    color += le;
+                    credit(&mut aovs.groups, scene, light, throughput * le);
+                });
+                return color;
+            }
+        };
+
+        let m = MaterialType::resolve(&hit_material, r, &rec);
+        let mut color = m.emitted(&rec).at_wavelength(wavelength);
+        let owner = scene.lights().iter().position(|l| l.owns(&hit_material));
+        credit(&mut aovs.groups, scene, owner, throughput * color);
+
+        if m.is_specular() {
+            let mut attenuation = black;
+            let (is_scattered, scattered) = m.scatter(r, &rec, &mut attenuation);
+            if is_scattered {
+                let attenuation = attenuation.at_wavelength(wavelength);
+                let next = Ray::with_wavelength(&rec.p, &scattered, wavelength);
+                color += attenuation
+                    * self.trace(&next, scene, depth + 1, throughput * attenuation, aovs);
+            }
+            return color;
+        }
+
+        for (index, light) in scene.lights().iter().enumerate() {
+            let ls = match light.sample_li(&rec.p) {
+                Some(ls) if ls.pdf > 0.0 && !ls.li.is_black() => ls,
+                _ => continue,
+            };
+            let f = m.eval(r, &rec, &ls.wi).at_wavelength(wavelength);
+            if f.is_black() {
+                continue;
+            }
+            let mut shadow_rec = HitRecord::new();
+            let shadow_ray = Ray::new(&rec.p, &ls.wi);
+            let (occluded, _) =
+                scene
+                    .world
+                    .hit(&shadow_ray, 0.001, ls.dist - 0.001, &mut shadow_rec);
+            if !occluded {
+                let direct = f * ls.li.at_wavelength(wavelength) / ls.pdf;
+                color += direct;
+                credit(&mut aovs.groups, scene, Some(index), throughput * direct);
+            }
+        }
+        color
+    }
+}
+
+// Whitted tracing takes no random decisions of its own, those of the
+// materials and lights go through `random_number`.
+impl Integrator for WhittedIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        self.li_with_aovs(r, scene, sampler, &mut PathAovs::default())
+    }
+
+    fn li_with_aovs(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        _sampler: &mut dyn Sampler,
+        aovs: &mut PathAovs,
+    ) -> Color {
+        self.trace(r, scene, 0, Color::new(1.0, 1.0, 1.0), aovs)
+    }
+
+    fn supports_aovs(&self) -> bool {
+        true
+    }
+}
+
+// Geometric normal of the first hit, mapped from [-1, 1] to [0, 1].
+pub struct NormalsIntegrator;
+
+impl Integrator for NormalsIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, _sampler: &mut dyn Sampler) -> Color {
+        let mut rec = HitRecord::new();
+        let (is_hit, _) = scene.world.hit(r, 0.001, f64::INFINITY, &mut rec);
+        if !is_hit {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let n = rec.outward_normal();
+        Color::new(n.x() + 1.0, n.y() + 1.0, n.z() + 1.0) * 0.5
+    }
+}
+
+// Distance to the first hit as a grey level, white at `far` and beyond.
+pub struct DepthIntegrator {
+    pub far: f64,
+}
+
+impl DepthIntegrator {
+    pub fn new(far: f64) -> Self {
+        Self { far }
+    }
+}
+
+impl Integrator for DepthIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, _sampler: &mut dyn Sampler) -> Color {
+        let mut rec = HitRecord::new();
+        let (is_hit, _) = scene.world.hit(r, 0.001, f64::INFINITY, &mut rec);
+        let depth = if is_hit {
+            f64::min(rec.t * r.direction().norm() / self.far, 1.0)
+        } else {
+            1.0
+        };
+        Color::new(depth, depth, depth)
+    }
+}
+
+// Share of the hemisphere above the first hit left open within `distance`,
+// estimated with cosine weighted rays.
+pub struct AmbientOcclusionIntegrator {
+    pub samples: u32,
+    pub distance: f64,
+}
+
+impl AmbientOcclusionIntegrator {
+    pub fn new(samples: u32, distance: f64) -> Self {
+        Self { samples, distance }
+    }
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        let mut rec = HitRecord::new();
+        let (is_hit, _) = scene.world.hit(r, 0.001, f64::INFINITY, &mut rec);
+        if !is_hit {
+            return Color::new(1.0, 1.0, 1.0);
+        }
+
+        let uvw = Onb::build_from_w(&rec.normal);
+        let mut open = 0;
+        for _ in 0..self.samples {
+            let (u1, u2) = sampler.get_2d();
+            let phi = 2.0 * PI * u1;
+            let sin_theta = u2.sqrt();
+            let dir = uvw.local(
+                sin_theta * phi.cos(),
+                sin_theta * phi.sin(),
+                f64::sqrt(1.0 - u2),
+            );
+            let mut occluder = HitRecord::new();
+            let (occluded, _) =
+                scene
+                    .world
+                    .hit(&Ray::new(&rec.p, &dir), 0.001, self.distance, &mut occluder);
+            if !occluded {
+                open += 1;
+            }
+        }
+        let ao = open as f64 / self.samples.max(1) as f64;
+        Color::new(ao, ao, ao)
+    }
+}
+
+// Russian roulette: terminate dark paths early, boosting the ones that survive
+// so the estimate stays unbiased. Returns whether the path continues.
+pub(crate) fn russian_roulette(
+    throughput: &mut Color,
+    depth: i32,
+    sampler: &mut dyn Sampler,
+) -> bool {
+    if depth < MIN_BOUNCES {
+        return true;
+    }
+    let survival = f64::min(throughput.max_component(), 0.95);
+    if sampler.get_1d() >= survival {
+        return false;
+    }
+    *throughput *= 1.0 / survival;
+    true
+}
+
+// Intersects the camera ray `r` with the scene, keeping the surface found for
+// the AOVs. Returns the material hit, if any.
+pub(crate) fn hit_first(
+    r: &Ray,
+    scene: &Scene,
+    rec: &mut HitRecord,
+    first_hit: &mut Option<FirstHit>,
+) -> Option<Rc<MaterialType>> {
+    let (object, material) = scene.world.hit_object(r, 0.001, f64::INFINITY, rec)?;
+    *first_hit = Some(FirstHit {
+        object,
+        material: Rc::clone(&material),
+        rec: *rec,
+    });
+    Some(material)
+}
+
+// Calls `visit` with the radiance of a ray leaving the scene coming from each
+// light, from the environment and the sun lights. The environment has no
+// light when it isn't importance sampled.
+pub(crate) fn background_by_light(
+    r: &Ray,
+    scene: &Scene,
+    bsdf_pdf: f64,
+    mut visit: impl FnMut(Option<usize>, Color),
+) {
+    let unit_direction = Vec3::unit_vector(r.direction());
+    let mut env = scene.environment.radiance(&unit_direction);
+    if bsdf_pdf > 0.0 {
+        env *= power_heuristic(bsdf_pdf, scene.environment_pdf(&unit_direction));
+    }
+    let env_light = scene
+        .lights()
+        .iter()
+        .position(|l| matches!(l, LightType::Environment(_)));
+    visit(env_light, env);
+
+    let light_count = scene.lights().len() as f64;
+    for (index, light) in scene.lights().iter().enumerate() {
+        if let LightType::Sun(sun) = light {
+            let mut le = sun.le(&unit_direction);
+            if bsdf_pdf > 0.0 && !le.is_black() {
+                let light_pdf = sun.pdf_li(r.origin(), &unit_direction) / light_count;
+                le *= power_heuristic(bsdf_pdf, light_pdf);
+            }
+            visit(Some(index), le);
+        }
+    }
+}
+
+// Adds `c` to the pass of the light group of `light`, if groups are tracked.
+pub(crate) fn credit(groups: &mut [Color], scene: &Scene, light: Option<usize>, c: Color) {
+    if let Some(light) = light {
+        if let Some(group) = groups.get_mut(scene.light_group(light)) {
+            *group += c;
+        }
+    }
+}
+
+// Next-event estimation: picks one light, traces a shadow ray towards it and
+// weights the result against BSDF sampling with the power heuristic. Also
+// returns the index of the light picked, if there is one.
+fn sample_light(
+    r: &Ray,
+    rec: &HitRecord,
+    m: &MaterialType,
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+) -> (Color, Option<usize>) {
+    let black = Color::new(0.0, 0.0, 0.0);
+    if scene.lights().is_empty() {
+        return (black, None);
+    }
+
+    let light_count = scene.lights().len();
+    let index = usize::min(
+        (sampler.get_1d() * light_count as f64) as usize,
+        light_count - 1,
+    );
+    let light = &scene.lights()[index];
+
+    let ls = match light.sample_li(&rec.p) {
+        Some(ls) if ls.pdf > 0.0 && !ls.li.is_black() => ls,
+        _ => return (black, Some(index)),
+    };
+
+    let f = m.eval(r, rec, &ls.wi).at_wavelength(r.wavelength);
+    if f.is_black() {
+        return (black, Some(index));
+    }
+
+    let mut shadow_rec = HitRecord::new();
+    let shadow_ray = Ray::new(&rec.p, &ls.wi);
+    let (occluded, _) = scene
+        .world
+        .hit(&shadow_ray, 0.001, ls.dist - 0.001, &mut shadow_rec);
+    if occluded {
+        return (black, Some(index));
+    }
+
+    let light_pdf = ls.pdf / light_count as f64;
+    let weight = if light.is_delta() {
+        1.0
+    } else {
+        power_heuristic(light_pdf, m.pdf(r, rec, &ls.wi))
+    };
+
+    let direct = f * ls.li.at_wavelength(r.wavelength) * (weight / light_pdf);
+    (direct, Some(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+    use crate::scene_file;
+    use crate::utils::random_number;
+
+    #[test]
+    fn light_groups_add_up_to_the_radiance() {
+        let scene = scene_file::parse(
+            "environment gradient bottom=0,0,0 top=0,0,0\n\
+             material floor lambertian albedo=0.7,0.7,0.7\n\
+             material red lambertian albedo=0.7,0.15,0.1\n\
+             material glass dielectric ir=1.5\n\
+             material warm light emit=30,22,12\n\
+             sphere center=0,-100.5,-1 radius=100 material=floor\n\
+             sphere center=-0.55,0,-1.2 radius=0.5 material=red\n\
+             sphere center=0.6,0,-1.3 radius=0.5 material=glass\n\
+             sphere center=-1.2,1.3,-0.6 radius=0.15 material=warm group=key\n\
+             light point position=1.5,1.5,0 intensity=2,3,5 group=fill\n",
+        )
+        .unwrap();
+        let integrators: Vec<Box<dyn Integrator>> = vec![
+            Box::new(PathIntegrator::new(8).with_caustics(PhotonMap::build(&scene, 20_000, 8))),
+            Box::new(BdptIntegrator::new(8)),
+            Box::new(WhittedIntegrator::new(8)),
+        ];
+
+        let black = Color::new(0.0, 0.0, 0.0);
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        for integrator in integrators {
+            let mut totals = vec![black; scene.group_names.len()];
+            for _ in 0..2000 {
+                let dir = Vec3::new(
+                    random_number() * 2.0 - 1.0,
+                    -0.3 - 0.5 * random_number(),
+                    -1.0,
+                );
+                let r = Ray::new(&origin, &dir);
+                let mut aovs = PathAovs::new(scene.group_names.len());
+                let color = integrator.li_with_aovs(&r, &scene, &mut IndependentSampler, &mut aovs);
+
+                let sum = aovs.groups.iter().fold(black, |sum, c| sum + *c);
+                for (a, b) in [
+                    (sum.r(), color.r()),
+                    (sum.g(), color.g()),
+                    (sum.b(), color.b()),
+                ] {
+                    assert!((a - b).abs() < 1e-9 * (1.0 + b.abs()));
+                }
+                assert!(aovs.first_hit.is_some());
+                for (total, c) in totals.iter_mut().zip(&aovs.groups) {
+                    *total += *c;
+                }
+            }
+            // Both the key and the fill group receive light.
+            assert!(totals[1].max_component() > 0.0);
+            assert!(totals[2].max_component() > 0.0);
+        }
+    }
+
+    #[test]
+    fn scenes_without_lights_render() {
+        let scene = scene_file::parse(
+            "material floor lambertian albedo=0.7,0.7,0.7\n\
+             sphere center=0,-100.5,-1 radius=100 material=floor\n",
+        )
+        .unwrap();
+        let (origin, dir) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, -0.5, -1.0));
+        let r = Ray::new(&origin, &dir);
+        for name in NAMES {
+            let integrator = by_name(name, 8, None).unwrap();
+            let mut aovs = PathAovs::new(scene.group_names.len());
+            let color = integrator.li_with_aovs(&r, &scene, &mut IndependentSampler, &mut aovs);
+            assert!(color.r().is_finite() && color.g().is_finite() && color.b().is_finite());
+        }
+    }
+}
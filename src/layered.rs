@@ -0,0 +1,270 @@
+use crate::color::Color;
+use crate::fresnel::fresnel_dielectric;
+use crate::hittable::HitRecord;
+use crate::material::{shading_frame, LightReflection, MaterialType};
+use crate::microfacet::TrowbridgeReitz;
+use crate::node::Node;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::utils::random_number;
+use crate::vec::Vec3;
+use std::rc::Rc;
+
+// Keeps the coat out of the delta case so it can be light sampled.
+const MIN_COAT_ALPHA: f64 = 0.002;
+
+// Dielectric coat over any base material. Light reaching the base is refracted
+// through the top interface, attenuated by the layer on the way in and out and
+// handed to the base with the refracted directions. Light bouncing between the
+// base and the underside of the coat is accounted for in aggregate, assuming
+// the base scatters diffusely. The index of refraction is fixed, as the
+// light bouncing inside the layer is averaged for it once.
+pub struct Layered {
+    pub base: Rc<MaterialType>,
+    pub ior: f64,
+    pub roughness: Rc<Node>,
+    // Absorption coefficient of the coat and its thickness, in the same units.
+    pub absorption: Rc<Node>,
+    pub thickness: Rc<Node>,
+    // Share of the light scattered diffusely by the base that the coat
+    // reflects back down onto it.
+    internal_reflectance: f64,
+}
+
+// Parameters of the coat evaluated at a hit.
+struct Coat {
+    distribution: TrowbridgeReitz,
+    absorption: Color,
+    thickness: f64,
+}
+
+impl Layered {
+    pub fn new(base: Rc<MaterialType>, ior: f64, roughness: f64) -> Self {
+        // Cosine weighted average of the Fresnel reflectance from the outside,
+        // by reciprocity only (1 - F) / ior^2 of the light leaving a diffuse
+        // base escapes through the coat.
+        let steps = 64;
+        let mut average = 0.0;
+        for i in 0..steps {
+            let cos_theta = (i as f64 + 0.5) / steps as f64;
+            average += 2.0 * cos_theta * fresnel_dielectric(cos_theta, ior) / steps as f64;
+        }
+
+        Self {
+            base,
+            ior,
+            roughness: Node::uniform(roughness),
+            absorption: Node::uniform(0.0),
+            thickness: Node::uniform(1.0),
+            internal_reflectance: 1.0 - (1.0 - average) / (ior * ior),
+        }
+    }
+
+    pub fn with_roughness(mut self, roughness: Rc<Node>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn with_absorption(mut self, absorption: Rc<Node>) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    pub fn with_thickness(mut self, thickness: Rc<Node>) -> Self {
+        self.thickness = thickness;
+        self
+    }
+
+    fn coat(&self, r_in: &Ray, rec: &HitRecord) -> Coat {
+        let roughness = self.roughness.eval_scalar(r_in, rec).clamp(0.0, 1.0);
+        let alpha = f64::max(
+            TrowbridgeReitz::roughness_to_alpha(roughness),
+            MIN_COAT_ALPHA,
+        );
+        Coat {
+            distribution: TrowbridgeReitz::new(alpha, alpha),
+            absorption: self.absorption.eval(r_in, rec),
+            thickness: self.thickness.eval_scalar(r_in, rec).max(0.0),
+        }
+    }
+
+    // Direction inside the layer matching the local direction `w` outside.
+    fn inside(&self, w: &Vec3) -> Vec3 {
+        let (x, y) = (w.x() / self.ior, w.y() / self.ior);
+        Vec3::new(x, y, f64::sqrt(f64::max(0.0, 1.0 - x * x - y * y)))
+    }
+
+    // Direction outside the layer matching `w` inside, None when it is totally
+    // internally reflected.
+    fn outside(&self, w: &Vec3) -> Option<Vec3> {
+        let (x, y) = (w.x() * self.ior, w.y() * self.ior);
+        let z2 = 1.0 - x * x - y * y;
+        (z2 > 0.0).then(|| Vec3::new(x, y, z2.sqrt()))
+    }
+
+    fn transmittance(coat: &Coat, wo: &Vec3, wi: &Vec3) -> Color {
+        if coat.absorption.is_black() {
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        let path = coat.thickness * (1.0 / wo.z() + 1.0 / wi.z());
+        (coat.absorption * -path).exp()
+    }
+
+    // Probability of sampling the coat rather than the base.
+    fn coat_probability(&self, wo: &Vec3) -> f64 {
+        let f = fresnel_dielectric(wo.z(), self.ior);
+        let albedo = self.base.get_albedo().luminance();
+        let base = (1.0 - f) * if albedo > 0.0 { albedo } else { 0.5 };
+        (f / (f + base)).clamp(0.1, 0.9)
+    }
+
+    fn coat_eval(&self, coat: &Coat, wo: &Vec3, wi: &Vec3) -> f64 {
+        let wm = Vec3::unit_vector(&(wi + wo));
+        let f = fresnel_dielectric(wo.dot(&wm), self.ior);
+        f * coat.distribution.d(&wm) * coat.distribution.g(wo, wi) / (4.0 * wo.z())
+    }
+
+    fn coat_pdf(coat: &Coat, wo: &Vec3, wi: &Vec3) -> f64 {
+        let wm = Vec3::unit_vector(&(wi + wo));
+        coat.distribution.d_visible(wo, &wm) / (4.0 * wo.dot(&wm).abs())
+    }
+
+    // Base lobe seen from outside the coat, BSDF times cosine, for a non
+    // specular base.
+    fn base_eval(
+        &self,
+        coat: &Coat,
+        r_in: &Ray,
+        rec: &HitRecord,
+        uvw: &Onb,
+        wo: &Vec3,
+        wi: &Vec3,
+    ) -> Color {
+        let (wo_in, wi_in) = (self.inside(wo), self.inside(wi));
+        let inner_dir = -uvw.local_vec(&wo_in);
+        let inner = Ray::with_wavelength(r_in.origin(), &inner_dir, r_in.wavelength);
+        let f = self.base.eval(&inner, rec, &uvw.local_vec(&wi_in));
+        if f.is_black() {
+            return f;
+        }
+
+        let transmission = (1.0 - fresnel_dielectric(wo.z(), self.ior))
+            * (1.0 - fresnel_dielectric(wi.z(), self.ior));
+        // Light coming back down from the coat after each diffuse bounce.
+        let round_trip = self.base.get_albedo()
+            * (coat.absorption * (-4.0 * coat.thickness)).exp()
+            * self.internal_reflectance;
+        let bounces = Color::new(
+            1.0 / (1.0 - round_trip.r()),
+            1.0 / (1.0 - round_trip.g()),
+            1.0 / (1.0 - round_trip.b()),
+        );
+
+        // The base cosine is inside the layer, the solid angle is compressed
+        // by the refraction.
+        f * Self::transmittance(coat, &wo_in, &wi_in)
+            * bounces
+            * (transmission * wi.z() / (self.ior * self.ior * wi_in.z()))
+    }
+
+    fn base_pdf(&self, r_in: &Ray, rec: &HitRecord, uvw: &Onb, wo: &Vec3, wi: &Vec3) -> f64 {
+        let (wo_in, wi_in) = (self.inside(wo), self.inside(wi));
+        let inner_dir = -uvw.local_vec(&wo_in);
+        let inner = Ray::with_wavelength(r_in.origin(), &inner_dir, r_in.wavelength);
+        let pdf = self.base.pdf(&inner, rec, &uvw.local_vec(&wi_in));
+        pdf * wi.z() / (self.ior * self.ior * wi_in.z())
+    }
+}
+
+impl LightReflection for Layered {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return (false, rec.normal);
+        }
+        let p_coat = self.coat_probability(&wo);
+        let coat = self.coat(r_in, rec);
+
+        let wi = if random_number() < p_coat {
+            let wm = coat
+                .distribution
+                .sample_wm(&wo, random_number(), random_number());
+            let wi = Vec3::reflect(&-wo, &wm);
+            if wi.z() <= 0.0 {
+                return (false, rec.normal);
+            }
+            if self.base.is_specular() {
+                let f = fresnel_dielectric(wo.dot(&wm), self.ior);
+                let shadowing = coat.distribution.g(&wo, &wi) / coat.distribution.g1(&wo);
+                let weight = f * shadowing / p_coat;
+                *attenuation = Color::new(weight, weight, weight);
+                return (true, uvw.local_vec(&wi));
+            }
+            wi
+        } else {
+            let wo_in = self.inside(&wo);
+            let inner_dir = -uvw.local_vec(&wo_in);
+            let inner = Ray::with_wavelength(r_in.origin(), &inner_dir, r_in.wavelength);
+            let mut base_attenuation = Color::new(0.0, 0.0, 0.0);
+            let (scattered, direction) = self.base.scatter(&inner, rec, &mut base_attenuation);
+            if !scattered {
+                return (false, rec.normal);
+            }
+            let wi_in = uvw.to_local(&Vec3::unit_vector(&direction));
+            if wi_in.z() <= 0.0 {
+                return (false, rec.normal);
+            }
+            let wi = match self.outside(&wi_in) {
+                Some(wi) => wi,
+                None => return (false, rec.normal),
+            };
+            if self.base.is_specular() {
+                // Radiance scaling on the way in and out cancels out.
+                let transmission = (1.0 - fresnel_dielectric(wo.z(), self.ior))
+                    * (1.0 - fresnel_dielectric(wi.z(), self.ior));
+                *attenuation = base_attenuation
+                    * Self::transmittance(&coat, &wo_in, &wi_in)
+                    * (transmission / (1.0 - p_coat));
+                return (true, uvw.local_vec(&wi));
+            }
+            wi
+        };
+
+        let scattered = uvw.local_vec(&wi);
+        let pdf = self.pdf(r_in, rec, &scattered);
+        if pdf <= 0.0 {
+            return (false, rec.normal);
+        }
+        *attenuation = self.eval(r_in, rec, &scattered) / pdf;
+        (true, scattered)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+        if self.base.is_specular() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let coat = self.coat(r_in, rec);
+        let f = self.coat_eval(&coat, &wo, &wi);
+        Color::new(f, f, f) + self.base_eval(&coat, r_in, rec, &uvw, &wo, &wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+        if self.base.is_specular() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+
+        let p_coat = self.coat_probability(&wo);
+        let coat = self.coat(r_in, rec);
+        p_coat * Self::coat_pdf(&coat, &wo, &wi)
+            + (1.0 - p_coat) * self.base_pdf(r_in, rec, &uvw, &wo, &wi)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+}
@@ -0,0 +1,567 @@
+use crate::color::Color;
+use crate::environment::Environment;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{LightReflection, MaterialType};
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::sphere::Sphere;
+use crate::triangle::Triangle;
+use crate::utils::{degrees_to_radian, random_number};
+use crate::vec::Vec3;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// Incoming light at a shading point, `wi` points from the point towards the light.
+pub struct LightSample {
+    pub wi: Vec3,
+    pub li: Color,
+    pub dist: f64,
+    pub pdf: f64,
+}
+
+// Light leaving an emitter, starting a path traced from the lights.
+pub struct EmissionSample {
+    pub origin: Vec3,
+    // Outward normal of the emitting surface, zero for point lights.
+    pub normal: Vec3,
+    pub direction: Vec3,
+    pub le: Color,
+    // Area pdf of the origin, one for lights reduced to a point.
+    pub pdf_pos: f64,
+    // Solid angle pdf of the direction.
+    pub pdf_dir: f64,
+}
+
+pub trait LightSampling {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample>;
+
+    // Solid angle pdf of `sample_li` choosing the direction `wi` from `p`.
+    fn pdf_li(&self, p: &Vec3, wi: &Vec3) -> f64;
+
+    // Samples a ray leaving the light. Lights at infinity can't start paths.
+    fn sample_le(&self) -> Option<EmissionSample> {
+        None
+    }
+
+    // Position and direction pdfs of `sample_le` emitting towards `dir` from
+    // the point `p` on the light.
+    fn pdf_le(&self, _p: &Vec3, _dir: &Vec3) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+
+    // Delta lights can't be hit by rays, they are only reached by light sampling.
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+pub enum LightType {
+    Area(AreaLight),
+    Triangle(TriangleLight),
+    Point(PointLight),
+    Spot(SpotLight),
+    Directional(DirectionalLight),
+    Environment(EnvironmentLight),
+    Sun(SunLight),
+}
+
+impl LightType {
+    // Whether this light is the emitter owning the material `m` that was hit.
+    pub fn owns(&self, m: &Rc<MaterialType>) -> bool {
+        match self {
+            LightType::Area(a) => Rc::ptr_eq(&a.shape.m, m),
+            LightType::Triangle(t) => Rc::ptr_eq(&t.shape.m, m),
+            _ => false,
+        }
+    }
+}
+
+impl LightSampling for LightType {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample> {
+        match self {
+            LightType::Area(a) => a.sample_li(p),
+            LightType::Triangle(t) => t.sample_li(p),
+            LightType::Point(l) => l.sample_li(p),
+            LightType::Spot(l) => l.sample_li(p),
+            LightType::Directional(l) => l.sample_li(p),
+            LightType::Environment(l) => l.sample_li(p),
+            LightType::Sun(l) => l.sample_li(p),
+        }
+    }
+
+    fn pdf_li(&self, p: &Vec3, wi: &Vec3) -> f64 {
+        match self {
+            LightType::Area(a) => a.pdf_li(p, wi),
+            LightType::Triangle(t) => t.pdf_li(p, wi),
+            LightType::Point(l) => l.pdf_li(p, wi),
+            LightType::Spot(l) => l.pdf_li(p, wi),
+            LightType::Directional(l) => l.pdf_li(p, wi),
+            LightType::Environment(l) => l.pdf_li(p, wi),
+            LightType::Sun(l) => l.pdf_li(p, wi),
+        }
+    }
+
+    fn sample_le(&self) -> Option<EmissionSample> {
+        match self {
+            LightType::Area(a) => a.sample_le(),
+            LightType::Triangle(t) => t.sample_le(),
+            LightType::Point(l) => l.sample_le(),
+            LightType::Spot(l) => l.sample_le(),
+            LightType::Directional(l) => l.sample_le(),
+            LightType::Environment(l) => l.sample_le(),
+            LightType::Sun(l) => l.sample_le(),
+        }
+    }
+
+    fn pdf_le(&self, p: &Vec3, dir: &Vec3) -> (f64, f64) {
+        match self {
+            LightType::Area(a) => a.pdf_le(p, dir),
+            LightType::Triangle(t) => t.pdf_le(p, dir),
+            LightType::Point(l) => l.pdf_le(p, dir),
+            LightType::Spot(l) => l.pdf_le(p, dir),
+            LightType::Directional(l) => l.pdf_le(p, dir),
+            LightType::Environment(l) => l.pdf_le(p, dir),
+            LightType::Sun(l) => l.pdf_le(p, dir),
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        match self {
+            LightType::Area(a) => a.is_delta(),
+            LightType::Triangle(t) => t.is_delta(),
+            LightType::Point(l) => l.is_delta(),
+            LightType::Spot(l) => l.is_delta(),
+            LightType::Directional(l) => l.is_delta(),
+            LightType::Environment(l) => l.is_delta(),
+            LightType::Sun(l) => l.is_delta(),
+        }
+    }
+}
+
+// Emissive sphere, also present in the world so BSDF sampled rays can hit it.
+pub struct AreaLight {
+    pub shape: Rc<Sphere>,
+}
+
+impl AreaLight {
+    pub fn new(shape: Rc<Sphere>) -> Self {
+        Self { shape }
+    }
+
+    pub fn normal_at(&self, p: &Vec3) -> Vec3 {
+        Vec3::unit_vector(&(p - self.shape.center))
+    }
+}
+
+impl LightSampling for AreaLight {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample> {
+        let (direction, pdf) = self.shape.sample_direction(p);
+        let wi = Vec3::unit_vector(&direction);
+
+        let mut rec = HitRecord::new();
+        let (is_hit, m) = self
+            .shape
+            .hit(&Ray::new(p, &wi), 0.001, f64::INFINITY, &mut rec);
+        if !is_hit {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            li: m.emitted(&rec),
+            dist: rec.t,
+            pdf,
+        })
+    }
+
+    fn pdf_li(&self, p: &Vec3, wi: &Vec3) -> f64 {
+        self.shape.pdf_value(p, wi)
+    }
+
+    // Uniform point on the sphere, cosine weighted direction around its normal.
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let normal = Vec3::random_unit_vector();
+        let origin = self.shape.center + normal * self.shape.radius;
+        let mut direction = normal + Vec3::random_unit_vector();
+        if direction.near_zero() {
+            direction = normal;
+        }
+        let direction = Vec3::unit_vector(&direction);
+
+        let mut rec = HitRecord::new();
+        rec.p = origin;
+        rec.normal = normal;
+        rec.front_face = true;
+        let (pdf_pos, pdf_dir) = self.pdf_le(&origin, &direction);
+
+        Some(EmissionSample {
+            origin,
+            normal,
+            direction,
+            le: self.shape.m.emitted(&rec),
+            pdf_pos,
+            pdf_dir,
+        })
+    }
+
+    fn pdf_le(&self, p: &Vec3, dir: &Vec3) -> (f64, f64) {
+        let area = 4.0 * PI * self.shape.radius * self.shape.radius;
+        let cosine = self.normal_at(p).dot(&Vec3::unit_vector(dir));
+        (1.0 / area, f64::max(0.0, cosine) / PI)
+    }
+}
+
+// Emissive triangle of a mesh, which keeps its own copy in the world. Each
+// one needs a material of its own for `owns` to tell the triangles apart.
+pub struct TriangleLight {
+    pub shape: Triangle,
+    area: f64,
+    normal: Vec3,
+}
+
+impl TriangleLight {
+    pub fn new(shape: Triangle) -> Self {
+        let cross = (shape.v1 - shape.v0).cross(&(shape.v2 - shape.v0));
+        Self {
+            area: 0.5 * cross.norm(),
+            normal: Vec3::unit_vector(&cross),
+            shape,
+        }
+    }
+
+    pub fn normal_at(&self, _p: &Vec3) -> Vec3 {
+        self.normal
+    }
+
+    // Uniform point over the triangle's area.
+    fn sample_point(&self) -> Vec3 {
+        let su = random_number().sqrt();
+        let (b0, b1) = (1.0 - su, random_number() * su);
+        self.shape.v0 * b0 + self.shape.v1 * b1 + self.shape.v2 * (1.0 - b0 - b1)
+    }
+
+    fn emission(&self, p: &Vec3) -> Color {
+        let mut rec = HitRecord::new();
+        rec.p = *p;
+        rec.normal = self.normal;
+        rec.front_face = true;
+        self.shape.m.emitted(&rec)
+    }
+}
+
+impl LightSampling for TriangleLight {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample> {
+        let to_light = self.sample_point() - p;
+        let dist = to_light.norm();
+        let wi = to_light / dist;
+        // Only the front of the triangle emits.
+        let cosine = -self.normal.dot(&wi);
+        if cosine <= 0.0 || dist == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            li: self.emission(&(p + wi * dist)),
+            dist,
+            pdf: dist * dist / (cosine * self.area),
+        })
+    }
+
+    // The geometry alone is tested, an opacity mask would make the pdf random.
+    fn pdf_li(&self, p: &Vec3, wi: &Vec3) -> f64 {
+        let hit = self.shape.intersect(&Ray::new(p, wi), 0.001, f64::INFINITY);
+        let cosine = -self.normal.dot(&Vec3::unit_vector(wi));
+        let t = match hit {
+            Some((t, _, _)) if cosine > 0.0 => t,
+            _ => return 0.0,
+        };
+        let dist = t * wi.norm();
+        dist * dist / (cosine * self.area)
+    }
+
+    // Uniform point, cosine weighted direction around the front normal.
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let origin = self.sample_point();
+        let mut direction = self.normal + Vec3::random_unit_vector();
+        if direction.near_zero() {
+            direction = self.normal;
+        }
+        let direction = Vec3::unit_vector(&direction);
+        let (pdf_pos, pdf_dir) = self.pdf_le(&origin, &direction);
+
+        Some(EmissionSample {
+            origin,
+            normal: self.normal,
+            direction,
+            le: self.emission(&origin),
+            pdf_pos,
+            pdf_dir,
+        })
+    }
+
+    fn pdf_le(&self, _p: &Vec3, dir: &Vec3) -> (f64, f64) {
+        let cosine = self.normal.dot(&Vec3::unit_vector(dir));
+        (1.0 / self.area, f64::max(0.0, cosine) / PI)
+    }
+}
+
+// Isotropic light at a single point, with inverse-square falloff.
+pub struct PointLight {
+    pub position: Vec3,
+    pub intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl LightSampling for PointLight {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let dist = to_light.norm();
+
+        Some(LightSample {
+            wi: to_light / dist,
+            li: self.intensity * (1.0 / (dist * dist)),
+            dist,
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vec3, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let direction = Vec3::random_unit_vector();
+        Some(EmissionSample {
+            origin: self.position,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            direction,
+            le: self.intensity,
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * PI),
+        })
+    }
+
+    fn pdf_le(&self, _p: &Vec3, _dir: &Vec3) -> (f64, f64) {
+        (1.0, 1.0 / (4.0 * PI))
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+// Point light restricted to a cone, with a smooth falloff between the
+// falloff start angle and the total cone angle.
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub intensity: Color,
+    cos_total_width: f64,
+    cos_falloff_start: f64,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vec3,
+        target: Vec3,
+        intensity: Color,
+        total_width: f64,
+        falloff_start: f64,
+    ) -> Self {
+        Self {
+            position,
+            direction: Vec3::unit_vector(&(target - position)),
+            intensity,
+            cos_total_width: f64::cos(degrees_to_radian(total_width)),
+            cos_falloff_start: f64::cos(degrees_to_radian(f64::min(falloff_start, total_width))),
+        }
+    }
+
+    fn falloff(&self, w: &Vec3) -> f64 {
+        let cos_theta = self.direction.dot(w);
+        if cos_theta < self.cos_total_width {
+            return 0.0;
+        }
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        let delta =
+            (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+        delta * delta * (3.0 - 2.0 * delta)
+    }
+}
+
+impl LightSampling for SpotLight {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let dist = to_light.norm();
+        let wi = to_light / dist;
+
+        let falloff = self.falloff(&-wi);
+        if falloff == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            li: self.intensity * (falloff / (dist * dist)),
+            dist,
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vec3, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
+    // Directions are uniform within the cone.
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let uvw = Onb::build_from_w(&self.direction);
+        let direction =
+            Vec3::unit_vector(&uvw.local_vec(&Vec3::random_to_cone(self.cos_total_width)));
+        Some(EmissionSample {
+            origin: self.position,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            direction,
+            le: self.intensity * self.falloff(&direction),
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (2.0 * PI * (1.0 - self.cos_total_width)),
+        })
+    }
+
+    fn pdf_le(&self, _p: &Vec3, dir: &Vec3) -> (f64, f64) {
+        if self.direction.dot(&Vec3::unit_vector(dir)) < self.cos_total_width {
+            return (1.0, 0.0);
+        }
+        (1.0, 1.0 / (2.0 * PI * (1.0 - self.cos_total_width)))
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+// Infinitely distant light such as the sun, all its rays are parallel.
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub radiance: Color,
+}
+
+impl DirectionalLight {
+    // `direction` is the direction the light travels in.
+    pub fn new(direction: Vec3, radiance: Color) -> Self {
+        Self {
+            direction: Vec3::unit_vector(&direction),
+            radiance,
+        }
+    }
+}
+
+impl LightSampling for DirectionalLight {
+    fn sample_li(&self, _p: &Vec3) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction,
+            li: self.radiance,
+            dist: f64::INFINITY,
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vec3, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+// Importance sampled environment, so the sky is reached by shadow rays too.
+pub struct EnvironmentLight {
+    pub env: Rc<dyn Environment>,
+}
+
+impl EnvironmentLight {
+    pub fn new(env: Rc<dyn Environment>) -> Self {
+        Self { env }
+    }
+}
+
+impl LightSampling for EnvironmentLight {
+    fn sample_li(&self, _p: &Vec3) -> Option<LightSample> {
+        let (wi, pdf) = self.env.sample()?;
+
+        Some(LightSample {
+            wi,
+            li: self.env.radiance(&wi),
+            dist: f64::INFINITY,
+            pdf,
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vec3, wi: &Vec3) -> f64 {
+        self.env.pdf(wi)
+    }
+}
+
+// Distant light covering a small disk of the sky, like the sun. Unlike a
+// directional light it has a size, so it casts soft shadows and can be seen
+// by rays leaving the scene.
+pub struct SunLight {
+    pub direction: Vec3,
+    pub radiance: Color,
+    cos_theta_max: f64,
+}
+
+impl SunLight {
+    // `direction` points towards the sun, `irradiance` is measured on a
+    // surface facing it and `angular_radius` is in degrees.
+    pub fn new(direction: Vec3, irradiance: Color, angular_radius: f64) -> Self {
+        let cos_theta_max = f64::cos(degrees_to_radian(angular_radius));
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        Self {
+            direction: Vec3::unit_vector(&direction),
+            radiance: irradiance * (1.0 / solid_angle),
+            cos_theta_max,
+        }
+    }
+
+    // Radiance seen by a ray escaping the scene in direction `dir`.
+    pub fn le(&self, dir: &Vec3) -> Color {
+        if Vec3::unit_vector(dir).dot(&self.direction) < self.cos_theta_max {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.radiance
+    }
+}
+
+impl LightSampling for SunLight {
+    fn sample_li(&self, _p: &Vec3) -> Option<LightSample> {
+        let uvw = Onb::build_from_w(&self.direction);
+        let wi = Vec3::unit_vector(&uvw.local_vec(&Vec3::random_to_cone(self.cos_theta_max)));
+
+        Some(LightSample {
+            wi,
+            li: self.radiance,
+            dist: f64::INFINITY,
+            pdf: 1.0 / (2.0 * PI * (1.0 - self.cos_theta_max)),
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vec3, wi: &Vec3) -> f64 {
+        if Vec3::unit_vector(wi).dot(&self.direction) < self.cos_theta_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * (1.0 - self.cos_theta_max))
+    }
+}
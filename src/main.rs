@@ -1,20 +1,49 @@
+pub mod aov;
+pub mod bdpt;
+pub mod bump;
 pub mod camera;
 pub mod color;
+pub mod distribution;
+pub mod environment;
+pub mod fresnel;
 pub mod hittable;
 pub mod hittable_list;
+pub mod integrator;
+pub mod layered;
+pub mod light;
 pub mod material;
+pub mod measured;
+pub mod mesh;
+pub mod microfacet;
+pub mod mlt;
+pub mod node;
+pub mod obj;
+pub mod onb;
+pub mod photon;
 pub mod point;
+pub mod principled;
 pub mod ray;
+pub mod sampler;
+pub mod scene;
+pub mod scene_file;
+pub mod sky;
+pub mod spectrum;
 pub mod sphere;
+pub mod subsurface;
+pub mod texture;
+pub mod triangle;
 pub mod utils;
 pub mod vec;
 
+use aov::{Aovs, PathAovs};
 use camera::Camera;
 use color::Color;
-use hittable::{HitRecord, Hittable};
-use hittable_list::HittableList;
-use material::{Dielectric, Lambertian, LightReflection, MaterialType, Metal};
+use light::{AreaLight, LightType};
+use material::{DiffuseLight, Lambertian, MaterialType};
+use photon::PhotonMap;
 use ray::Ray;
+use sampler::{IndependentSampler, Sampler};
+use scene::Scene;
 use sphere::Sphere;
 use std::f64::consts::PI;
 use std::rc::Rc;
@@ -28,52 +57,166 @@ fn main() {
     let samples_per_pixel: u32 = 100;
     let max_depth = 50;
 
-    // World
-    let r = f64::cos(PI / 4.0);
-    let mut world = HittableList::new();
-
-    // let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
-    let material_left = MaterialType::Lambertian(Lambertian::new(Color::new(0.0, 0.0, 1.0)));
-    let material_right = MaterialType::Lambertian(Lambertian::new(Color::new(1.0, 0.0, 0.0)));
-    // let material_left = MaterialType::Dielectric(Dielectric::new(1.5));
-    // let material_right = MaterialType::Metal(Metal::new(Color::new(0.8, 0.6, 0.2), 1.0));
-
-    // let center_ground = Vec3::new(0.0, -100.5, -1.0);
-    // let center = Vec3::new(0.0, 0.0, -1.0);
-    let center_left = Vec3::new(-r, 0.0, -1.0);
-    let center_right = Vec3::new(r, 0.0, -1.0);
+    // Usage: ray-tracer-rust [scene file] [--integrator=<name>] [--spectral] [--photons] [--mlt]
+    //                       [--aov=<prefix>]
+    // Integrators: path (default), bdpt, whitted, normals, depth, ao. --bdpt is
+    // kept as a shorthand for --integrator=bdpt. With --aov the compositing
+    // passes are written next to the image, see `aov`, for the path, bdpt and
+    // whitted integrators.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(flag) = args
+        .iter()
+        .find(|a| a.starts_with("--") && !is_known_flag(a))
+    {
+        eprintln!("unknown option {}", flag);
+        eprintln!(
+            "usage: ray-tracer-rust [scene file] [--integrator=<name>] [--spectral] \
+             [--photons] [--mlt] [--aov=<prefix>]"
+        );
+        std::process::exit(1);
+    }
+    let spectral = args.iter().any(|a| a == "--spectral");
+    let integrator_name = args
+        .iter()
+        .find_map(|a| a.strip_prefix("--integrator="))
+        .or_else(|| args.iter().any(|a| a == "--bdpt").then_some("bdpt"))
+        .unwrap_or(integrator::NAMES[0]);
+    if !integrator::NAMES.contains(&integrator_name) {
+        eprintln!(
+            "unknown integrator {}, expected one of: {}",
+            integrator_name,
+            integrator::NAMES.join(", ")
+        );
+        std::process::exit(1);
+    }
+    let mlt = args.iter().any(|a| a == "--mlt");
+    let aov_prefix = args.iter().find_map(|a| a.strip_prefix("--aov="));
 
-    // let sphere_ground = Rc::new(Sphere::new(&center_ground, 100.0, Rc::new(material_ground)));
-    // let sphere_center = Rc::new(Sphere::new(&center, 0.5, Rc::new(material_center)));
-    let sphere_left = Rc::new(Sphere::new(&center_left, r, Rc::new(material_left)));
-    let sphere_right = Rc::new(Sphere::new(&center_right, r, Rc::new(material_right)));
+    // World
+    let scene = match args.iter().find(|a| !a.starts_with("--")) {
+        Some(path) => scene_file::load(path).expect("could not load scene"),
+        None => default_scene(),
+    };
 
-    // world.add(sphere_ground);
-    // world.add(sphere_center);
-    world.add(sphere_left);
-    world.add(sphere_right);
+    // Caustics are estimated from a photon map instead of being path traced.
+    let mut photons = args.iter().any(|a| a == "--photons");
+    if photons && integrator_name != "path" {
+        eprintln!("--photons is only used by the path integrator, ignoring it");
+        photons = false;
+    }
+    let caustics = photons.then(|| PhotonMap::build(&scene, photon::PHOTON_COUNT, max_depth));
+    let integrator = integrator::by_name(integrator_name, max_depth, caustics)
+        .expect("integrator names are checked above");
+    if aov_prefix.is_some() && !integrator.supports_aovs() {
+        eprintln!("the {} integrator doesn't render AOVs", integrator_name);
+        std::process::exit(1);
+    }
 
     // Camera
     let cam = Camera::new(90.0, aspect_ratio);
+    let sample = |u: f64, v: f64, sampler: &mut dyn Sampler, path: &mut PathAovs| {
+        let dir = cam.get_dir(u, v);
+        if spectral {
+            let (lambda, pdf) = spectrum::sample_wavelength();
+            let r = Ray::with_wavelength(&cam.origin, &dir, Some(lambda));
+            let value = integrator.li_with_aovs(&r, &scene, sampler, path).r();
+            for group in path.groups.iter_mut() {
+                *group = spectrum::spectral_to_rgb(group.r(), lambda, pdf);
+            }
+            spectrum::spectral_to_rgb(value, lambda, pdf)
+        } else {
+            let r = Ray::new(&cam.origin, &dir);
+            integrator.li_with_aovs(&r, &scene, sampler, path)
+        }
+    };
 
     // Render
     println!("P3\n{} {}\n255", image_width, image_heigth);
 
+    if mlt {
+        if aov_prefix.is_some() {
+            eprintln!("AOVs aren't rendered with --mlt");
+        }
+        let image = mlt::render(
+            image_width,
+            image_heigth,
+            samples_per_pixel,
+            |u, v, sampler| sample(u, v, sampler, &mut PathAovs::default()),
+        );
+        for pixel_color in image {
+            pixel_color.write_color(1);
+        }
+        return;
+    }
+
+    let mut aovs = aov_prefix.map(|_| Aovs::new(image_width, image_heigth, &scene));
+    let black = Color::new(0.0, 0.0, 0.0);
+    let mut path = PathAovs::new(aovs.as_ref().map_or(0, |a| a.group_count()));
+
     for j in (0..image_heigth).rev() {
         for i in 0..image_width {
-            let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-            for _s in 0..samples_per_pixel {
+            let pixel = ((image_heigth - 1 - j) * image_width + i) as usize;
+            let mut pixel_color = black;
+            for s in 0..samples_per_pixel {
                 let u = (i as f64 + random_number()) / (image_width as f64 - 1.0);
                 let v = (j as f64 + random_number()) / (image_heigth as f64 - 1.0);
-
-                let dir = cam.get_dir(u, v);
-                let r = Ray::new(&cam.origin, &dir);
-                pixel_color += ray_color(&r, &world, max_depth);
+                path.clear();
+                pixel_color += sample(u, v, &mut IndependentSampler, &mut path);
+
+                if let Some(aovs) = &mut aovs {
+                    let dir = cam.get_dir(u, v);
+                    let r = Ray::new(&cam.origin, &dir);
+                    aovs.add_sample(pixel, s == 0, &r, &path);
+                }
             }
 
             pixel_color.write_color(samples_per_pixel);
         }
     }
+
+    if let (Some(aovs), Some(prefix)) = (aovs, aov_prefix) {
+        aovs.write(prefix, samples_per_pixel)
+            .expect("could not write AOVs");
+    }
+}
+
+fn is_known_flag(arg: &str) -> bool {
+    matches!(arg, "--spectral" | "--photons" | "--mlt" | "--bdpt")
+        || arg.starts_with("--integrator=")
+        || arg.starts_with("--aov=")
+}
+
+fn default_scene() -> Scene {
+    let r = f64::cos(PI / 4.0);
+    let mut scene = Scene::new();
+
+    // let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+    let material_left = MaterialType::Lambertian(Lambertian::new(Color::new(0.0, 0.0, 1.0)));
+    let material_right = MaterialType::Lambertian(Lambertian::new(Color::new(1.0, 0.0, 0.0)));
+    let material_light = MaterialType::DiffuseLight(DiffuseLight::new(Color::new(4.0, 4.0, 4.0)));
+    // let material_left = MaterialType::Dielectric(Dielectric::new(1.5));
+    // let material_right = MaterialType::Metal(Metal::new(Color::new(0.8, 0.6, 0.2), 1.0));
+
+    // let center_ground = Vec3::new(0.0, -100.5, -1.0);
+    // let center = Vec3::new(0.0, 0.0, -1.0);
+    let center_left = Vec3::new(-r, 0.0, -1.0);
+    let center_right = Vec3::new(r, 0.0, -1.0);
+    let center_light = Vec3::new(0.0, 1.5, -1.0);
+
+    // let sphere_ground = Rc::new(Sphere::new(center_ground, 100.0, Rc::new(material_ground)));
+    // let sphere_center = Rc::new(Sphere::new(center, 0.5, Rc::new(material_center)));
+    let sphere_left = Rc::new(Sphere::new(center_left, r, Rc::new(material_left)));
+    let sphere_right = Rc::new(Sphere::new(center_right, r, Rc::new(material_right)));
+    let sphere_light = Rc::new(Sphere::new(center_light, 0.25, Rc::new(material_light)));
+
+    // scene.world.add(sphere_ground);
+    // scene.world.add(sphere_center);
+    scene.world.add(sphere_left);
+    scene.world.add(sphere_right);
+    scene.world.add(sphere_light.clone());
+    scene.add_light(LightType::Area(AreaLight::new(sphere_light)), "default");
+
+    scene
 }
 
 // fn ray_color(r: &Ray) -> Color {
@@ -91,38 +234,6 @@ fn main() {
 //     Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t
 // }
 
-fn ray_color<T: Hittable>(r: &Ray, world: &HittableList<T>, depth: i32) -> Color {
-    let mut rec = HitRecord {
-        p: Vec3::new(0.0, 0.0, 0.0),
-        normal: Vec3::new(0.0, 0.0, 0.0),
-        t: 0.0,
-        front_face: false,
-    };
-
-    // If we've exceeded the ray bounce limit, no more light is gathered.
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
-
-    let (is_hit, m) = world.hit(r, 0.001, f64::INFINITY, &mut rec);
-    if is_hit {
-        let mut attenuation = Color::new(0.0, 0.0, 0.0);
-
-        let (is_scattered, scatter_direction) = m.scatter(r, &rec, &mut attenuation);
-        let scattered_ray = Ray::new(&rec.p, &scatter_direction);
-
-        if is_scattered {
-            return attenuation * ray_color(&scattered_ray, &world, depth - 1);
-        }
-
-        return Color::new(0.0, 0.0, 0.0);
-    }
-
-    let unit_direction = Vec3::unit_vector(r.dir);
-    let t = 0.5 * (unit_direction.y() + 1.0);
-    Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t
-}
-
 // fn hit_sphere(center: &Vec3, radius: f64, r: &Ray) -> f64 {
 //     let oc = r.origin() - center;
 
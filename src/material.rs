@@ -1,123 +1,439 @@
+use num::complex::Complex64;
 use num::traits::Pow;
 
+use crate::bump::Bumped;
 use crate::color::Color;
+use crate::fresnel::{fresnel_conductor, fresnel_dielectric, FilmFresnel, ThinFilm};
 use crate::hittable::HitRecord;
+use crate::layered::Layered;
+use crate::measured::Measured;
+use crate::microfacet::TrowbridgeReitz;
+use crate::node::Node;
+use crate::onb::Onb;
+use crate::principled::Principled;
 use crate::ray::Ray;
-use crate::utils::random_number;
+use crate::spectrum::Dispersion;
+use crate::subsurface::{Medium, Subsurface};
+use crate::texture::ImageTexture;
+use crate::utils::{degrees_to_radian, random_number};
 use crate::vec::Vec3;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// Reference wavelength for the index of refraction of dispersive media, in nm.
+const SODIUM_D_LINE: f64 = 589.3;
 
 pub trait LightReflection {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3);
+
+    // BSDF value times the cosine term for a given scattered direction. Only
+    // meaningful for non specular materials, used by explicit light sampling.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Vec3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // Solid angle pdf of `scatter` returning the given direction.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // Specular materials scatter into a single direction, they can't be
+    // evaluated for an arbitrary light direction.
+    fn is_specular(&self) -> bool {
+        false
+    }
 }
 
 pub enum MaterialType {
     Lambertian(Lambertian),
     Metal(Metal),
     Dielectric(Dielectric),
+    DiffuseLight(DiffuseLight),
+    Conductor(Conductor),
+    RoughDielectric(RoughDielectric),
+    Principled(Principled),
+    OrenNayar(OrenNayar),
+    Translucent(Translucent),
+    Bumped(Bumped),
+    Masked(Masked),
+    Layered(Layered),
+    Subsurface(Subsurface),
+    Measured(Measured),
+    Mix(Mix),
 }
 
 impl MaterialType {
     pub fn get_albedo(&self) -> Color {
-        let albedo = match self {
-            MaterialType::Lambertian(l) => l.albedo,
-            MaterialType::Metal(m) => m.albedo,
+        match self {
+            MaterialType::Lambertian(l) => l.albedo.mean(),
+            MaterialType::Metal(m) => m.albedo.mean(),
+            MaterialType::Principled(p) => p.base_color.mean(),
+            MaterialType::OrenNayar(o) => o.albedo.mean(),
+            MaterialType::Translucent(t) => t.reflectance.mean(),
+            MaterialType::Bumped(b) => b.base.get_albedo(),
+            MaterialType::Masked(m) => m.base.get_albedo(),
+            MaterialType::Layered(l) => l.base.get_albedo(),
+            MaterialType::Subsurface(s) => s.albedo,
+            MaterialType::Measured(m) => m.albedo(),
+            MaterialType::Mix(m) => {
+                let w = m.weight.mean().luminance().clamp(0.0, 1.0);
+                m.a.get_albedo() * (1.0 - w) + m.b.get_albedo() * w
+            }
             _ => Color::new(0.0, 0.0, 0.0),
-        };
-        albedo
+        }
+    }
+
+    // Albedo at the hit, with the node graphs evaluated there rather than
+    // averaged like in `get_albedo`.
+    pub fn albedo_at(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        match self {
+            MaterialType::Lambertian(l) => l.albedo.eval(r_in, rec),
+            MaterialType::Metal(m) => m.albedo.eval(r_in, rec),
+            MaterialType::OrenNayar(o) => o.albedo.eval(r_in, rec),
+            MaterialType::Principled(p) => p.base_color.eval(r_in, rec),
+            MaterialType::Translucent(t) => t.reflectance.eval(r_in, rec),
+            MaterialType::Bumped(b) => b.base.albedo_at(r_in, rec),
+            MaterialType::Masked(m) => m.base.albedo_at(r_in, rec),
+            MaterialType::Layered(l) => l.base.albedo_at(r_in, rec),
+            MaterialType::Mix(m) => {
+                let w = m.weight.eval(r_in, rec).luminance().clamp(0.0, 1.0);
+                m.a.albedo_at(r_in, rec) * (1.0 - w) + m.b.albedo_at(r_in, rec) * w
+            }
+            _ => self.get_albedo(),
+        }
+    }
+
+    // Whether a ray should ignore the surface at this hit, for materials with
+    // an opacity mask. Partially opaque points let a random fraction through.
+    pub fn passes_through(&self, rec: &HitRecord) -> bool {
+        match self {
+            MaterialType::Masked(m) => {
+                random_number() >= m.opacity(rec) || m.base.passes_through(rec)
+            }
+            MaterialType::Bumped(b) => b.base.passes_through(rec),
+            MaterialType::Layered(l) => l.base.passes_through(rec),
+            MaterialType::Mix(m) => {
+                // The ray isn't known while intersecting, the weight is taken
+                // as seen along the normal like for the emission.
+                let towards = -rec.normal;
+                m.choose(&Ray::new(&rec.p, &towards), rec)
+                    .passes_through(rec)
+            }
+            _ => false,
+        }
+    }
+
+    // Material actually scattering at this hit: mixes pick one of their
+    // children at random, so that the integrator sees a single material with
+    // a well defined `is_specular`.
+    pub fn resolve(material: &Rc<Self>, r_in: &Ray, rec: &HitRecord) -> Rc<Self> {
+        match material.as_ref() {
+            MaterialType::Mix(m) => Self::resolve(m.choose(r_in, rec), r_in, rec),
+            _ => Rc::clone(material),
+        }
+    }
+
+    // Whether the material emits light somewhere, directly or through the
+    // materials it wraps or mixes.
+    pub fn is_emissive(&self) -> bool {
+        match self {
+            MaterialType::DiffuseLight(_) => true,
+            MaterialType::Mix(m) => m.a.is_emissive() || m.b.is_emissive(),
+            MaterialType::Bumped(b) => b.base.is_emissive(),
+            MaterialType::Masked(m) => m.base.is_emissive(),
+            _ => false,
+        }
+    }
+
+    // Medium filling the inside of objects made of this material.
+    pub fn medium(&self) -> Option<Medium> {
+        match self {
+            MaterialType::Subsurface(s) => Some(s.medium),
+            MaterialType::Bumped(b) => b.base.medium(),
+            MaterialType::Masked(m) => m.base.medium(),
+            MaterialType::Layered(l) => l.base.medium(),
+            _ => None,
+        }
     }
 
     pub fn get_fuzz(&self) -> f64 {
-        let fuzz = match self {
-            MaterialType::Metal(m) => m.fuzz,
+        match self {
+            MaterialType::Metal(m) => m.fuzz.mean().luminance(),
             _ => 0.0,
-        };
-        fuzz
+        }
     }
 
     pub fn get_ir(&self) -> f64 {
-        let ir = match self {
+        match self {
             MaterialType::Dielectric(d) => d.ir,
+            MaterialType::RoughDielectric(d) => d.ir,
             _ => 0.0,
-        };
-        ir
+        }
     }
 }
 impl LightReflection for MaterialType {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
-        let result = match self {
+        match self {
             MaterialType::Lambertian(l) => l.scatter(r_in, rec, attenuation),
             MaterialType::Metal(m) => m.scatter(r_in, rec, attenuation),
             MaterialType::Dielectric(d) => d.scatter(r_in, rec, attenuation),
-        };
-        result
+            MaterialType::DiffuseLight(d) => d.scatter(r_in, rec, attenuation),
+            MaterialType::Conductor(c) => c.scatter(r_in, rec, attenuation),
+            MaterialType::RoughDielectric(d) => d.scatter(r_in, rec, attenuation),
+            MaterialType::Principled(p) => p.scatter(r_in, rec, attenuation),
+            MaterialType::OrenNayar(o) => o.scatter(r_in, rec, attenuation),
+            MaterialType::Translucent(t) => t.scatter(r_in, rec, attenuation),
+            MaterialType::Bumped(b) => b.scatter(r_in, rec, attenuation),
+            MaterialType::Masked(m) => m.scatter(r_in, rec, attenuation),
+            MaterialType::Layered(l) => l.scatter(r_in, rec, attenuation),
+            MaterialType::Subsurface(s) => s.scatter(r_in, rec, attenuation),
+            MaterialType::Measured(m) => m.scatter(r_in, rec, attenuation),
+            MaterialType::Mix(m) => m.scatter(r_in, rec, attenuation),
+        }
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        match self {
+            MaterialType::Lambertian(l) => l.eval(r_in, rec, scattered),
+            MaterialType::Metal(m) => m.eval(r_in, rec, scattered),
+            MaterialType::Dielectric(d) => d.eval(r_in, rec, scattered),
+            MaterialType::DiffuseLight(d) => d.eval(r_in, rec, scattered),
+            MaterialType::Conductor(c) => c.eval(r_in, rec, scattered),
+            MaterialType::RoughDielectric(d) => d.eval(r_in, rec, scattered),
+            MaterialType::Principled(p) => p.eval(r_in, rec, scattered),
+            MaterialType::OrenNayar(o) => o.eval(r_in, rec, scattered),
+            MaterialType::Translucent(t) => t.eval(r_in, rec, scattered),
+            MaterialType::Bumped(b) => b.eval(r_in, rec, scattered),
+            MaterialType::Masked(m) => m.eval(r_in, rec, scattered),
+            MaterialType::Layered(l) => l.eval(r_in, rec, scattered),
+            MaterialType::Subsurface(s) => s.eval(r_in, rec, scattered),
+            MaterialType::Measured(m) => m.eval(r_in, rec, scattered),
+            MaterialType::Mix(m) => m.eval(r_in, rec, scattered),
+        }
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        match self {
+            MaterialType::Lambertian(l) => l.pdf(r_in, rec, scattered),
+            MaterialType::Metal(m) => m.pdf(r_in, rec, scattered),
+            MaterialType::Dielectric(d) => d.pdf(r_in, rec, scattered),
+            MaterialType::DiffuseLight(d) => d.pdf(r_in, rec, scattered),
+            MaterialType::Conductor(c) => c.pdf(r_in, rec, scattered),
+            MaterialType::RoughDielectric(d) => d.pdf(r_in, rec, scattered),
+            MaterialType::Principled(p) => p.pdf(r_in, rec, scattered),
+            MaterialType::OrenNayar(o) => o.pdf(r_in, rec, scattered),
+            MaterialType::Translucent(t) => t.pdf(r_in, rec, scattered),
+            MaterialType::Bumped(b) => b.pdf(r_in, rec, scattered),
+            MaterialType::Masked(m) => m.pdf(r_in, rec, scattered),
+            MaterialType::Layered(l) => l.pdf(r_in, rec, scattered),
+            MaterialType::Subsurface(s) => s.pdf(r_in, rec, scattered),
+            MaterialType::Measured(m) => m.pdf(r_in, rec, scattered),
+            MaterialType::Mix(m) => m.pdf(r_in, rec, scattered),
+        }
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        match self {
+            MaterialType::Lambertian(l) => l.emitted(rec),
+            MaterialType::Metal(m) => m.emitted(rec),
+            MaterialType::Dielectric(d) => d.emitted(rec),
+            MaterialType::DiffuseLight(d) => d.emitted(rec),
+            MaterialType::Conductor(c) => c.emitted(rec),
+            MaterialType::RoughDielectric(d) => d.emitted(rec),
+            MaterialType::Principled(p) => p.emitted(rec),
+            MaterialType::OrenNayar(o) => o.emitted(rec),
+            MaterialType::Translucent(t) => t.emitted(rec),
+            MaterialType::Bumped(b) => b.emitted(rec),
+            MaterialType::Masked(m) => m.emitted(rec),
+            MaterialType::Layered(l) => l.emitted(rec),
+            MaterialType::Subsurface(s) => s.emitted(rec),
+            MaterialType::Measured(m) => m.emitted(rec),
+            MaterialType::Mix(m) => m.emitted(rec),
+        }
+    }
+
+    fn is_specular(&self) -> bool {
+        match self {
+            MaterialType::Lambertian(l) => l.is_specular(),
+            MaterialType::Metal(m) => m.is_specular(),
+            MaterialType::Dielectric(d) => d.is_specular(),
+            MaterialType::DiffuseLight(d) => d.is_specular(),
+            MaterialType::Conductor(c) => c.is_specular(),
+            MaterialType::RoughDielectric(d) => d.is_specular(),
+            MaterialType::Principled(p) => p.is_specular(),
+            MaterialType::OrenNayar(o) => o.is_specular(),
+            MaterialType::Translucent(t) => t.is_specular(),
+            MaterialType::Bumped(b) => b.is_specular(),
+            MaterialType::Masked(m) => m.is_specular(),
+            MaterialType::Layered(l) => l.is_specular(),
+            MaterialType::Subsurface(s) => s.is_specular(),
+            MaterialType::Measured(m) => m.is_specular(),
+            MaterialType::Mix(m) => m.is_specular(),
+        }
     }
 }
 
 pub struct Lambertian {
-    pub albedo: Color,
+    pub albedo: Rc<Node>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Self {
-        Self { albedo }
+        Self {
+            albedo: Node::constant(albedo),
+        }
+    }
+
+    pub fn with_albedo(mut self, albedo: Rc<Node>) -> Self {
+        self.albedo = albedo;
+        self
     }
 }
 
 impl LightReflection for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
-        let mut scatter_direction = &rec.normal + Vec3::random_unit_vector();
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
 
         if scatter_direction.near_zero() {
-            scatter_direction = rec.normal.clone();
+            scatter_direction = rec.normal;
         }
 
-        *attenuation = self.albedo;
+        *attenuation = self.albedo.eval(r_in, rec);
         (true, scatter_direction)
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        let cosine = rec.normal.dot(&Vec3::unit_vector(scattered));
+        if cosine <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.albedo.eval(r_in, rec) * (cosine / PI)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        let cosine = rec.normal.dot(&Vec3::unit_vector(scattered));
+        f64::max(cosine, 0.0) / PI
+    }
 }
 
 pub struct Metal {
-    pub albedo: Color,
-    pub fuzz: f64,
+    pub albedo: Rc<Node>,
+    pub fuzz: Rc<Node>,
 }
 
 impl Metal {
     pub fn new(albedo: Color, f: f64) -> Self {
         Self {
-            albedo: albedo,
-            fuzz: if f < 1.0 { f } else { 1.0 },
+            albedo: Node::constant(albedo),
+            fuzz: Node::uniform(if f < 1.0 { f } else { 1.0 }),
         }
     }
+
+    pub fn with_albedo(mut self, albedo: Rc<Node>) -> Self {
+        self.albedo = albedo;
+        self
+    }
+
+    pub fn with_fuzz(mut self, fuzz: Rc<Node>) -> Self {
+        self.fuzz = fuzz;
+        self
+    }
 }
 
 impl LightReflection for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
         let reflected = Vec3::reflect(&Vec3::unit_vector(r_in.direction()), &rec.normal);
 
-        let scatter_direction = reflected + Vec3::random_in_unit_sphere() * self.fuzz;
-        *attenuation = self.albedo;
+        let fuzz = self.fuzz.eval_scalar(r_in, rec).clamp(0.0, 1.0);
+        let scatter_direction = reflected + Vec3::random_in_unit_sphere() * fuzz;
+        *attenuation = self.albedo.eval(r_in, rec);
 
-        (
-            (Ray::new(
-                &rec.p,
-                &(&reflected + &Vec3::random_in_unit_sphere() * self.fuzz),
-            )
-            .direction()
-            .dot(&rec.normal))
-                > 0.0,
-            scatter_direction,
-        )
+        (scatter_direction.dot(&rec.normal) > 0.0, scatter_direction)
+    }
+
+    fn is_specular(&self) -> bool {
+        true
     }
 }
 
+// Smooth glass. Its parameters describe the medium inside the object rather
+// than its surface, so they stay fixed instead of being driven by nodes.
 pub struct Dielectric {
     pub ir: f64,
+    // Absorption coefficient per unit length inside the medium.
+    pub absorption: Color,
+    pub dispersion: Option<Dispersion>,
+    // Film reflectance seen from the outside and from the inside.
+    pub film: Option<[FilmFresnel; 2]>,
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Self {
-        Dielectric { ir }
+        Dielectric {
+            ir,
+            absorption: Color::new(0.0, 0.0, 0.0),
+            dispersion: None,
+            film: None,
+        }
+    }
+
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        let (black, white) = (Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        self.film = Some([
+            FilmFresnel::new(film, 1.0, white * self.ir, black),
+            FilmFresnel::new(film, self.ir, white, black),
+        ]);
+        self
+    }
+
+    pub fn with_absorption(mut self, absorption: Color) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    // Outside of spectral mode the index at the sodium D line is used.
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Self {
+        self.ir = dispersion.ior(SODIUM_D_LINE);
+        self.dispersion = Some(dispersion);
+        match self.film.take() {
+            Some([outside, _]) => self.with_thin_film(outside.film),
+            None => self,
+        }
+    }
+
+    fn ior(&self, r_in: &Ray) -> f64 {
+        match (self.dispersion, r_in.wavelength) {
+            (Some(dispersion), Some(lambda)) => dispersion.ior(lambda),
+            _ => self.ir,
+        }
     }
+
+    // Reflectance of the film on the side of `rec`. Spectral rays see it over
+    // the dispersed index at their wavelength rather than the tabulated `ir`.
+    fn film_reflectance(
+        &self,
+        films: &[FilmFresnel; 2],
+        cos_theta: f64,
+        r_in: &Ray,
+        rec: &HitRecord,
+    ) -> Color {
+        if let (Some(_), Some(lambda)) = (self.dispersion, r_in.wavelength) {
+            let ir = self.ior(r_in);
+            let (eta_i, substrate) = if rec.front_face { (1.0, ir) } else { (ir, 1.0) };
+            let r =
+                films[0]
+                    .film
+                    .reflectance(cos_theta, eta_i, Complex64::new(substrate, 0.0), lambda);
+            return Color::new(r, r, r);
+        }
+        let film = if rec.front_face { &films[0] } else { &films[1] };
+        film.evaluate(cos_theta, r_in.wavelength)
+    }
+
     pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
         let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
         r0 = r0 * r0;
@@ -127,32 +443,1007 @@ impl Dielectric {
 
 impl LightReflection for Dielectric {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+        *attenuation = medium_transmittance(&self.absorption, r_in, rec);
 
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
-        };
+        let ir = self.ior(r_in);
+        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
         let unit_direction = Vec3::unit_vector(r_in.direction());
 
         let cos_theta = f64::min(-unit_direction.dot(&rec.normal), 1.0);
         let sin_theta = f64::sqrt(1.0 - cos_theta.pow(2));
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
+        if cannot_refract {
+            return (
+                true,
+                Vec3::reflect(&Vec3::unit_vector(r_in.direction()), &rec.normal),
+            );
+        }
 
-        let scatter_direction: Vec3;
+        // A thin film makes the reflectance colored, the branch is then picked
+        // by its average and the difference made up in the weight.
+        let reflectance = match &self.film {
+            Some(films) => self.film_reflectance(films, cos_theta, r_in, rec),
+            None => {
+                let r = Self::reflectance(cos_theta, refraction_ratio);
+                Color::new(r, r, r)
+            }
+        };
+        let p_reflect = (reflectance.r() + reflectance.g() + reflectance.b()) / 3.0;
 
-        if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random_number() {
-            scatter_direction = Vec3::reflect(&Vec3::unit_vector(r_in.direction()), &rec.normal);
+        let scatter_direction = if p_reflect > random_number() {
+            *attenuation *= reflectance / p_reflect;
+            Vec3::reflect(&Vec3::unit_vector(r_in.direction()), &rec.normal)
         } else {
-            scatter_direction = Vec3::refract(
+            let transmittance = Color::new(1.0, 1.0, 1.0) + reflectance * -1.0;
+            *attenuation *= transmittance / (1.0 - p_reflect);
+            Vec3::refract(
                 &Vec3::unit_vector(r_in.direction()),
                 &rec.normal,
                 refraction_ratio,
-            );
+            )
+        };
+
+        (true, scatter_direction)
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+pub struct DiffuseLight {
+    pub emit: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self { emit }
+    }
+}
+
+impl LightReflection for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _attenuation: &mut Color) -> (bool, Vec3) {
+        (false, Vec3::new(0.0, 0.0, 0.0))
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        if !rec.front_face {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.emit
+    }
+}
+
+// Local shading frame of a hit, with the direction towards the viewer in it.
+pub fn shading_frame(r_in: &Ray, rec: &HitRecord) -> (Onb, Vec3) {
+    let uvw = Onb::build_from_normal_tangent(&rec.normal, &rec.tangent);
+    let wo = uvw.to_local(&-Vec3::unit_vector(r_in.direction()));
+    (uvw, wo)
+}
+
+// Beer-Lambert attenuation over the segment that ended at `rec`. A hit on the
+// back face means the ray travelled inside the medium to get there.
+fn medium_transmittance(absorption: &Color, r_in: &Ray, rec: &HitRecord) -> Color {
+    if rec.front_face || absorption.is_black() {
+        return Color::new(1.0, 1.0, 1.0);
+    }
+    let distance = rec.t * r_in.direction().norm();
+    (*absorption * -distance).exp()
+}
+
+// Refracts `wi`, pointing away from the surface on the side of `n`, through
+// an interface with relative index of refraction `eta`.
+pub fn refract(wi: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = n.dot(wi);
+    let sin2_theta_i = f64::max(0.0, 1.0 - cos_theta_i * cos_theta_i);
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = f64::sqrt(1.0 - sin2_theta_t);
+    Some(-*wi / eta + n * (cos_theta_i / eta - cos_theta_t))
+}
+
+// Microfacet conductor with a complex index of refraction eta + i k per channel.
+pub struct Conductor {
+    pub eta: Rc<Node>,
+    pub k: Rc<Node>,
+    // Roughness along the surface tangent and bitangent.
+    pub roughness_u: Rc<Node>,
+    pub roughness_v: Rc<Node>,
+    pub film: Option<FilmFresnel>,
+    // Turns the direction of anisotropy around the normal, a value of 1 being
+    // half a turn.
+    pub rotation: Option<Rc<Node>>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Self {
+            eta: Node::constant(eta),
+            k: Node::constant(k),
+            roughness_u: Node::uniform(roughness),
+            roughness_v: Node::uniform(roughness),
+            film: None,
+            rotation: None,
+        }
+    }
+
+    pub fn with_eta(mut self, eta: Rc<Node>) -> Self {
+        self.eta = eta;
+        self.tabulate_film()
+    }
+
+    pub fn with_k(mut self, k: Rc<Node>) -> Self {
+        self.k = k;
+        self.tabulate_film()
+    }
+
+    pub fn with_roughness(self, roughness: Rc<Node>) -> Self {
+        self.with_anisotropic_roughness(Rc::clone(&roughness), roughness)
+    }
+
+    // Brushed finish: roughness along the surface tangent and bitangent.
+    pub fn with_anisotropic_roughness(
+        mut self,
+        roughness_u: Rc<Node>,
+        roughness_v: Rc<Node>,
+    ) -> Self {
+        self.roughness_u = roughness_u;
+        self.roughness_v = roughness_v;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Rc<Node>) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    fn distribution(&self, r_in: &Ray, rec: &HitRecord) -> TrowbridgeReitz {
+        let distribution = TrowbridgeReitz::anisotropic(
+            self.roughness_u.eval_scalar(r_in, rec).clamp(0.0, 1.0),
+            self.roughness_v.eval_scalar(r_in, rec).clamp(0.0, 1.0),
+        );
+        if self.is_specular() {
+            distribution
+        } else {
+            distribution.at_least_rough()
         }
+    }
 
+    // Shading frame with its tangent along the brushing direction.
+    fn frame(&self, r_in: &Ray, rec: &HitRecord) -> (Onb, Vec3) {
+        let rotation = match &self.rotation {
+            Some(node) => node.eval_scalar(r_in, rec) * PI,
+            None => return shading_frame(r_in, rec),
+        };
+        let tangent = rec.tangent * rotation.cos() + rec.bitangent * rotation.sin();
+        let uvw = Onb::build_from_normal_tangent(&rec.normal, &tangent);
+        let wo = uvw.to_local(&-Vec3::unit_vector(r_in.direction()));
+        (uvw, wo)
+    }
+
+    // The film is tabulated over the average of the eta and k nodes, again
+    // whenever they change.
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(FilmFresnel::new(film, 1.0, self.eta.mean(), self.k.mean()));
+        self
+    }
+
+    fn tabulate_film(mut self) -> Self {
+        match self.film.take() {
+            Some(film) => self.with_thin_film(film.film),
+            None => self,
+        }
+    }
+
+    fn fresnel(&self, cos_theta_i: f64, r_in: &Ray, rec: &HitRecord) -> Color {
+        match &self.film {
+            Some(film) => film.evaluate(cos_theta_i, r_in.wavelength),
+            None => fresnel_conductor(
+                cos_theta_i,
+                &self.eta.eval(r_in, rec),
+                &self.k.eval(r_in, rec),
+            ),
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.143, 0.374, 1.442),
+            Color::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.200, 0.924, 1.102),
+            Color::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    // Conductor reflecting `reflectance` at normal incidence, with eta fixed
+    // at 1 and k solving ((eta - 1)^2 + k^2) / ((eta + 1)^2 + k^2) = r per channel.
+    pub fn from_reflectance(reflectance: Color, roughness: f64) -> Self {
+        let k = |r: f64| {
+            let r = r.clamp(0.0, 0.999);
+            2.0 * f64::sqrt(r / (1.0 - r))
+        };
+        Self::new(
+            Color::new(1.0, 1.0, 1.0),
+            Color::new(k(reflectance.r()), k(reflectance.g()), k(reflectance.b())),
+            roughness,
+        )
+    }
+}
+
+impl LightReflection for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        let (uvw, wo) = self.frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return (false, rec.normal);
+        }
+
+        let distribution = self.distribution(r_in, rec);
+        if distribution.is_smooth() {
+            *attenuation = self.fresnel(wo.z(), r_in, rec);
+            return (true, uvw.local(-wo.x(), -wo.y(), wo.z()));
+        }
+
+        let wm = distribution.sample_wm(&wo, random_number(), random_number());
+        let wi = Vec3::reflect(&-wo, &wm);
+        if wi.z() <= 0.0 {
+            return (false, rec.normal);
+        }
+
+        let shadowing = distribution.g(&wo, &wi) / distribution.g1(&wo);
+        *attenuation = self.fresnel(wo.dot(&wm), r_in, rec) * shadowing;
+        (true, uvw.local_vec(&wi))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        let (uvw, wo) = self.frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+        let distribution = self.distribution(r_in, rec);
+        if distribution.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let wm = Vec3::unit_vector(&(wi + wo));
+        let f = self.fresnel(wo.dot(&wm), r_in, rec);
+        f * (distribution.d(&wm) * distribution.g(&wo, &wi) / (4.0 * wo.z()))
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        let (uvw, wo) = self.frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+        let distribution = self.distribution(r_in, rec);
+        if distribution.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+
+        let wm = Vec3::unit_vector(&(wi + wo));
+        distribution.d_visible(&wo, &wm) / (4.0 * wo.dot(&wm).abs())
+    }
+
+    // Only a constant roughness can make the whole surface a mirror.
+    fn is_specular(&self) -> bool {
+        match (
+            self.roughness_u.as_constant(),
+            self.roughness_v.as_constant(),
+        ) {
+            (Some(u), Some(v)) => {
+                TrowbridgeReitz::anisotropic(u.luminance(), v.luminance()).is_smooth()
+            }
+            _ => false,
+        }
+    }
+}
+
+// Microfacet dielectric, reflecting and refracting through rough microfacets.
+// Only the roughness varies over the surface, the index and absorption belong
+// to the medium inside.
+pub struct RoughDielectric {
+    pub ir: f64,
+    pub roughness: Rc<Node>,
+    // Absorption coefficient per unit length inside the medium.
+    pub absorption: Color,
+    pub dispersion: Option<Dispersion>,
+}
+
+impl RoughDielectric {
+    pub fn new(ir: f64, roughness: f64) -> Self {
+        Self {
+            ir,
+            roughness: Node::uniform(roughness),
+            absorption: Color::new(0.0, 0.0, 0.0),
+            dispersion: None,
+        }
+    }
+
+    pub fn with_roughness(mut self, roughness: Rc<Node>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn with_absorption(mut self, absorption: Color) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    // Outside of spectral mode the index at the sodium D line is used.
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Self {
+        self.ir = dispersion.ior(SODIUM_D_LINE);
+        self.dispersion = Some(dispersion);
+        self
+    }
+
+    fn at(&self, r_in: &Ray, rec: &HitRecord) -> RoughInterface {
+        let roughness = self.roughness.eval_scalar(r_in, rec).clamp(0.0, 1.0);
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        let mut distribution = TrowbridgeReitz::new(alpha, alpha);
+        if !self.is_specular() {
+            distribution = distribution.at_least_rough();
+        }
+        RoughInterface {
+            ir: self.ir,
+            distribution,
+            absorption: self.absorption,
+            dispersion: self.dispersion,
+        }
+    }
+}
+
+impl LightReflection for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        self.at(r_in, rec).scatter(r_in, rec, attenuation)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        self.at(r_in, rec).eval(r_in, rec, scattered)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        self.at(r_in, rec).pdf(r_in, rec, scattered)
+    }
+
+    fn is_specular(&self) -> bool {
+        match self.roughness.as_constant() {
+            Some(c) => {
+                let alpha = TrowbridgeReitz::roughness_to_alpha(c.luminance());
+                TrowbridgeReitz::new(alpha, alpha).is_smooth()
+            }
+            None => false,
+        }
+    }
+}
+
+// Rough dielectric with its parameters evaluated at a hit, also serving as
+// the glass lobe of the principled BSDF.
+pub struct RoughInterface {
+    pub ir: f64,
+    pub distribution: TrowbridgeReitz,
+    pub absorption: Color,
+    pub dispersion: Option<Dispersion>,
+}
+
+impl RoughInterface {
+    // Relative index of refraction for a ray arriving on the side of `rec`.
+    fn eta(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let ir = match (self.dispersion, r_in.wavelength) {
+            (Some(dispersion), Some(lambda)) => dispersion.ior(lambda),
+            _ => self.ir,
+        };
+        if rec.front_face {
+            ir
+        } else {
+            1.0 / ir
+        }
+    }
+
+    // Half vector for a pair of directions, oriented towards +z, or None
+    // when the configuration can't happen on a microfacet.
+    fn half_vector(&self, wo: &Vec3, wi: &Vec3, eta: f64) -> Option<Vec3> {
+        let etap = if wi.z() > 0.0 { 1.0 } else { eta };
+        let wm = *wi * etap + wo;
+        if wi.z() == 0.0 || wm.near_zero() {
+            return None;
+        }
+
+        let mut wm = Vec3::unit_vector(&wm);
+        if wm.z() < 0.0 {
+            wm = -wm;
+        }
+
+        // Discard back facing microfacets.
+        if wm.dot(wi) * wi.z() < 0.0 || wm.dot(wo) * wo.z() < 0.0 {
+            return None;
+        }
+        Some(wm)
+    }
+}
+
+impl LightReflection for RoughInterface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let eta = self.eta(r_in, rec);
+        let transmittance = medium_transmittance(&self.absorption, r_in, rec);
+        *attenuation = transmittance;
+
+        let wm = if self.distribution.is_smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution
+                .sample_wm(&wo, random_number(), random_number())
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        let wi = if random_number() < reflectance {
+            let wi = Vec3::reflect(&-wo, &wm);
+            if wi.z() <= 0.0 {
+                return (false, rec.normal);
+            }
+            wi
+        } else {
+            match refract(&wo, &wm, eta) {
+                Some(wi) if wi.z() < 0.0 => wi,
+                _ => return (false, rec.normal),
+            }
+        };
+
+        if !self.distribution.is_smooth() {
+            let shadowing = self.distribution.g(&wo, &wi) / self.distribution.g1(&wo);
+            *attenuation = transmittance * shadowing;
+        }
+        (true, uvw.local_vec(&wi))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        if self.distribution.is_smooth() {
+            return black;
+        }
+
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+        let eta = self.eta(r_in, rec);
+        let wm = match self.half_vector(&wo, &wi, eta) {
+            Some(wm) => wm,
+            None => return black,
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        let dg = self.distribution.d(&wm) * self.distribution.g(&wo, &wi);
+        let value = if wi.z() > 0.0 {
+            dg * reflectance / (4.0 * wo.z().abs())
+        } else {
+            let denom = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+            (1.0 - reflectance) * dg * (wi.dot(&wm) * wo.dot(&wm)).abs() / (wo.z().abs() * denom)
+        };
+        medium_transmittance(&self.absorption, r_in, rec) * value
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+        let eta = self.eta(r_in, rec);
+        let wm = match self.half_vector(&wo, &wi, eta) {
+            Some(wm) => wm,
+            None => return 0.0,
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        let visible = self.distribution.d_visible(&wo, &wm);
+        if wi.z() > 0.0 {
+            visible / (4.0 * wo.dot(&wm).abs()) * reflectance
+        } else {
+            let denom = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+            visible * wi.dot(&wm).abs() / denom * (1.0 - reflectance)
+        }
+    }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+}
+
+// Rough diffuse surface made of V-shaped Lambertian facets, from Oren and
+// Nayar (1994). `sigma` is the standard deviation of the facet angle in degrees.
+pub struct OrenNayar {
+    pub albedo: Rc<Node>,
+    pub sigma: Rc<Node>,
+}
+
+impl OrenNayar {
+    pub fn new(albedo: Color, sigma: f64) -> Self {
+        Self {
+            albedo: Node::constant(albedo),
+            sigma: Node::uniform(sigma),
+        }
+    }
+
+    pub fn with_albedo(mut self, albedo: Rc<Node>) -> Self {
+        self.albedo = albedo;
+        self
+    }
+
+    pub fn with_sigma(mut self, sigma: Rc<Node>) -> Self {
+        self.sigma = sigma;
+        self
+    }
+
+    // The A and B terms of the model for the facet slope at the hit.
+    fn terms(&self, r_in: &Ray, rec: &HitRecord) -> (f64, f64) {
+        let sigma2 = degrees_to_radian(self.sigma.eval_scalar(r_in, rec)).powi(2);
+        (
+            1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            0.45 * sigma2 / (sigma2 + 0.09),
+        )
+    }
+}
+
+impl LightReflection for OrenNayar {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+
+        if scatter_direction.near_zero() {
+            scatter_direction = rec.normal;
+        }
+
+        let pdf = self.pdf(r_in, rec, &scatter_direction);
+        if pdf <= 0.0 {
+            return (false, scatter_direction);
+        }
+        *attenuation = self.eval(r_in, rec, &scatter_direction) * (1.0 / pdf);
         (true, scatter_direction)
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let sin_theta_i = f64::sqrt(f64::max(0.0, 1.0 - wi.z() * wi.z()));
+        let sin_theta_o = f64::sqrt(f64::max(0.0, 1.0 - wo.z() * wo.z()));
+
+        // Cosine of the azimuthal angle between the two directions.
+        let max_cos = if sin_theta_i > 1e-4 && sin_theta_o > 1e-4 {
+            let cos_phi = (wi.x() * wo.x() + wi.y() * wo.y()) / (sin_theta_i * sin_theta_o);
+            f64::max(0.0, cos_phi)
+        } else {
+            0.0
+        };
+
+        let (sin_alpha, tan_beta) = if wi.z() > wo.z() {
+            (sin_theta_o, sin_theta_i / wi.z())
+        } else {
+            (sin_theta_i, sin_theta_o / wo.z())
+        };
+
+        let (a, b) = self.terms(r_in, rec);
+        self.albedo.eval(r_in, rec) * ((a + b * max_cos * sin_alpha * tan_beta) / PI * wi.z())
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        let cosine = rec.normal.dot(&Vec3::unit_vector(scattered));
+        f64::max(cosine, 0.0) / PI
+    }
+}
+
+// Thin two sided diffuse surface, such as leaves or paper, reflecting part of
+// the light and diffusely transmitting part of it to the other side.
+pub struct Translucent {
+    pub reflectance: Rc<Node>,
+    pub transmittance: Rc<Node>,
+}
+
+impl Translucent {
+    pub fn new(reflectance: Color, transmittance: Color) -> Self {
+        Self {
+            reflectance: Node::constant(reflectance),
+            transmittance: Node::constant(transmittance),
+        }
+    }
+
+    pub fn with_reflectance(mut self, reflectance: Rc<Node>) -> Self {
+        self.reflectance = reflectance;
+        self
+    }
+
+    pub fn with_transmittance(mut self, transmittance: Rc<Node>) -> Self {
+        self.transmittance = transmittance;
+        self
+    }
+
+    // Probability of sampling the reflected rather than the transmitted side.
+    fn reflect_probability(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let r = self.reflectance.eval_scalar(r_in, rec);
+        let t = self.transmittance.eval_scalar(r_in, rec);
+        if r + t <= 0.0 {
+            return 0.5;
+        }
+        r / (r + t)
+    }
+}
+
+impl LightReflection for Translucent {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        let side = if random_number() < self.reflect_probability(r_in, rec) {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+
+        let mut scatter_direction = side + Vec3::random_unit_vector();
+        if scatter_direction.near_zero() {
+            scatter_direction = side;
+        }
+
+        let pdf = self.pdf(r_in, rec, &scatter_direction);
+        if pdf <= 0.0 {
+            return (false, scatter_direction);
+        }
+        *attenuation = self.eval(r_in, rec, &scatter_direction) * (1.0 / pdf);
+        (true, scatter_direction)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        let cosine = rec.normal.dot(&Vec3::unit_vector(scattered));
+        if cosine >= 0.0 {
+            self.reflectance.eval(r_in, rec) * (cosine / PI)
+        } else {
+            self.transmittance.eval(r_in, rec) * (-cosine / PI)
+        }
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        let cosine = rec.normal.dot(&Vec3::unit_vector(scattered));
+        let p = self.reflect_probability(r_in, rec);
+        if cosine >= 0.0 {
+            p * cosine / PI
+        } else {
+            (1.0 - p) * -cosine / PI
+        }
+    }
+}
+
+// Blend of two materials, `b` being picked with a probability given by the
+// luminance of the weight node, e.g. dust over metal or worn paint.
+pub struct Mix {
+    pub a: Rc<MaterialType>,
+    pub b: Rc<MaterialType>,
+    pub weight: Rc<Node>,
+}
+
+impl Mix {
+    pub fn new(a: Rc<MaterialType>, b: Rc<MaterialType>, weight: Rc<Node>) -> Self {
+        Self { a, b, weight }
+    }
+
+    fn weight(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        self.weight.eval(r_in, rec).luminance().clamp(0.0, 1.0)
+    }
+
+    pub fn choose(&self, r_in: &Ray, rec: &HitRecord) -> &Rc<MaterialType> {
+        if random_number() < self.weight(r_in, rec) {
+            &self.b
+        } else {
+            &self.a
+        }
+    }
+}
+
+// The integrator resolves mixes before shading, this is only used when a mix
+// sits below another material such as a bump map. It then behaves as the
+// weighted sum of both BSDFs.
+impl LightReflection for Mix {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        let chosen = self.choose(r_in, rec);
+        let (is_scattered, direction) = chosen.scatter(r_in, rec, attenuation);
+        if !is_scattered || self.is_specular() {
+            return (is_scattered, direction);
+        }
+        let pdf = self.pdf(r_in, rec, &direction);
+        if pdf <= 0.0 {
+            return (false, direction);
+        }
+        *attenuation = self.eval(r_in, rec, &direction) * (1.0 / pdf);
+        (true, direction)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        let w = self.weight(r_in, rec);
+        self.a.eval(r_in, rec, scattered) * (1.0 - w) + self.b.eval(r_in, rec, scattered) * w
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        let w = self.weight(r_in, rec);
+        self.a.pdf(r_in, rec, scattered) * (1.0 - w) + self.b.pdf(r_in, rec, scattered) * w
+    }
+
+    // Emission doesn't depend on the viewing direction, weights that do are
+    // taken looking straight at the surface.
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        let towards = -rec.normal;
+        let w = self.weight(&Ray::new(&rec.p, &towards), rec);
+        self.a.emitted(rec) * (1.0 - w) + self.b.emitted(rec) * w
+    }
+
+    // A specular child can't be evaluated, the scattered direction of the
+    // chosen child is then used as is.
+    fn is_specular(&self) -> bool {
+        self.a.is_specular() || self.b.is_specular()
+    }
+}
+
+// Cuts holes into another material following the luminance of an opacity
+// texture: black is fully cut out and white fully opaque.
+pub struct Masked {
+    pub base: Rc<MaterialType>,
+    pub mask: Rc<ImageTexture>,
+}
+
+impl Masked {
+    pub fn new(base: Rc<MaterialType>, mask: Rc<ImageTexture>) -> Self {
+        Self { base, mask }
+    }
+
+    pub fn opacity(&self, rec: &HitRecord) -> f64 {
+        self.mask.value(rec.u, rec.v).luminance().clamp(0.0, 1.0)
+    }
+}
+
+impl LightReflection for Masked {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        self.base.scatter(r_in, rec, attenuation)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        self.base.eval(r_in, rec, scattered)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        self.base.pdf(r_in, rec, scattered)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.base.emitted(rec)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::principled::PrincipledInput;
+
+    // Origin and direction of light arriving at `theta` degrees from the
+    // normal of a surface at the origin facing +z, on its front or back face.
+    fn hit(theta: f64, front_face: bool) -> (Vec3, Vec3, HitRecord) {
+        let theta = degrees_to_radian(theta);
+        let origin = Vec3::new(theta.sin(), 0.0, theta.cos());
+        let direction = -origin;
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.front_face = front_face;
+        rec.u = 0.5;
+        rec.v = 0.5;
+        rec.tangent = Vec3::new(1.0, 0.0, 0.0);
+        rec.bitangent = Vec3::new(0.0, 1.0, 0.0);
+        (origin, direction, rec)
+    }
+
+    // Scatters `m` many times, checking each weight against eval / pdf, then
+    // integrates the pdf over the sphere on a grid in spherical angles, finer
+    // in solid angle around the poles where the lobes peak. Samples that
+    // scatter nowhere, e.g. below the horizon, leave out the matching share of
+    // the pdf.
+    fn check_sampling(m: &MaterialType, theta: f64, front_face: bool) {
+        let (origin, direction, rec) = hit(theta, front_face);
+        let r_in = Ray::new(&origin, &direction);
+        let samples = 20000;
+        let mut scattered_count = 0;
+        for _ in 0..samples {
+            let mut attenuation = Color::new(0.0, 0.0, 0.0);
+            let (is_scattered, direction) = m.scatter(&r_in, &rec, &mut attenuation);
+            if !is_scattered {
+                continue;
+            }
+            scattered_count += 1;
+
+            let pdf = m.pdf(&r_in, &rec, &direction);
+            assert!(pdf > 0.0, "sampled a direction of zero pdf");
+            let expected = m.eval(&r_in, &rec, &direction) / pdf;
+            for (a, e) in [
+                (attenuation.r(), expected.r()),
+                (attenuation.g(), expected.g()),
+                (attenuation.b(), expected.b()),
+            ] {
+                assert!(
+                    (a - e).abs() <= 1e-6 * f64::max(1.0, e.abs()),
+                    "weight {} but eval / pdf {}",
+                    attenuation,
+                    expected
+                );
+            }
+        }
+
+        let (n_theta, n_phi) = (400, 800);
+        let mut integral = 0.0;
+        for i in 0..n_theta {
+            let polar = PI * (i as f64 + 0.5) / n_theta as f64;
+            for j in 0..n_phi {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n_phi as f64;
+                let w = Vec3::new(
+                    polar.sin() * phi.cos(),
+                    polar.sin() * phi.sin(),
+                    polar.cos(),
+                );
+                integral += m.pdf(&r_in, &rec, &w) * polar.sin();
+            }
+        }
+        integral *= 2.0 * PI * PI / (n_theta * n_phi) as f64;
+
+        let scattered = scattered_count as f64 / samples as f64;
+        assert!(
+            integral <= 1.02,
+            "pdf integrates to {} at {} degrees",
+            integral,
+            theta
+        );
+        assert!(
+            (integral - scattered).abs() < 0.03,
+            "pdf integrates to {} but {} of the samples scattered at {} degrees",
+            integral,
+            scattered,
+            theta
+        );
+    }
+
+    #[test]
+    fn conductors_sample_their_pdf() {
+        for theta in [0.0, 45.0, 75.0] {
+            for roughness in [0.3, 0.6, 1.0] {
+                let m = MaterialType::Conductor(Conductor::gold(roughness));
+                check_sampling(&m, theta, true);
+            }
+            let brushed = Conductor::aluminium(0.0)
+                .with_anisotropic_roughness(Node::uniform(0.3), Node::uniform(0.8));
+            check_sampling(&MaterialType::Conductor(brushed), theta, true);
+        }
+    }
+
+    #[test]
+    fn rough_dielectrics_sample_their_pdf() {
+        for theta in [0.0, 30.0, 60.0] {
+            for front_face in [true, false] {
+                let m = MaterialType::RoughDielectric(RoughDielectric::new(1.5, 0.5));
+                check_sampling(&m, theta, front_face);
+            }
+        }
+    }
+
+    #[test]
+    fn principled_sample_their_pdf() {
+        let base = || Principled::new(Color::new(0.8, 0.4, 0.2));
+        let materials = [
+            base(),
+            base().with_input(PrincipledInput::Metallic, Node::uniform(1.0)),
+            base()
+                .with_input(PrincipledInput::Sheen, Node::uniform(1.0))
+                .with_input(PrincipledInput::Clearcoat, Node::uniform(1.0))
+                .with_input(PrincipledInput::ClearcoatGloss, Node::uniform(0.5)),
+            base()
+                .with_input(PrincipledInput::Transmission, Node::uniform(0.7))
+                .with_input(PrincipledInput::Roughness, Node::uniform(0.4)),
+        ];
+        for p in materials {
+            let m = MaterialType::Principled(p);
+            for theta in [0.0, 45.0, 75.0] {
+                check_sampling(&m, theta, true);
+            }
+        }
+    }
+
+    #[test]
+    fn oren_nayar_and_translucent_sample_their_pdf() {
+        let rough = MaterialType::OrenNayar(OrenNayar::new(Color::new(0.8, 0.6, 0.4), 30.0));
+        let leaf = MaterialType::Translucent(Translucent::new(
+            Color::new(0.3, 0.6, 0.2),
+            Color::new(0.2, 0.5, 0.1),
+        ));
+        for theta in [0.0, 45.0, 75.0] {
+            check_sampling(&rough, theta, true);
+            check_sampling(&leaf, theta, true);
+        }
+    }
+
+    #[test]
+    fn layered_sample_their_pdf() {
+        let lambertian = Rc::new(MaterialType::Lambertian(Lambertian::new(Color::new(
+            0.7, 0.2, 0.1,
+        ))));
+        let metal = Rc::new(MaterialType::Conductor(Conductor::copper(0.4)));
+        let materials = [
+            Layered::new(Rc::clone(&lambertian), 1.5, 0.2),
+            Layered::new(lambertian, 1.5, 0.3)
+                .with_absorption(Node::constant(Color::new(0.1, 0.5, 1.0)))
+                .with_thickness(Node::uniform(0.5)),
+            Layered::new(metal, 1.3, 0.2),
+        ];
+        for l in materials {
+            let m = MaterialType::Layered(l);
+            for theta in [0.0, 45.0, 75.0] {
+                check_sampling(&m, theta, true);
+            }
+        }
+    }
+
+    // The boundary is a smooth dielectric: every sample either mirrors or
+    // refracts following Snell's law, in proportion to the Fresnel reflectance.
+    #[test]
+    fn subsurface_boundaries_are_smooth_dielectrics() {
+        let ior = 1.4;
+        let m = MaterialType::Subsurface(Subsurface::new(
+            Color::new(0.8, 0.5, 0.3),
+            Color::new(1.0, 0.5, 0.25),
+            ior,
+        ));
+        assert!(m.is_specular());
+
+        let theta: f64 = 50.0;
+        let (origin, direction, rec) = hit(theta, true);
+        let r_in = Ray::new(&origin, &direction);
+        let samples = 20000;
+        let mut reflected = 0;
+        for _ in 0..samples {
+            let mut attenuation = Color::new(0.0, 0.0, 0.0);
+            let (is_scattered, direction) = m.scatter(&r_in, &rec, &mut attenuation);
+            assert!(is_scattered);
+            for c in [attenuation.r(), attenuation.g(), attenuation.b()] {
+                assert!((c - 1.0).abs() < 1e-9);
+            }
+            assert!(m.eval(&r_in, &rec, &direction).is_black());
+            assert_eq!(m.pdf(&r_in, &rec, &direction), 0.0);
+
+            let w = Vec3::unit_vector(&direction);
+            if w.z() > 0.0 {
+                reflected += 1;
+                assert!((w.x() - r_in.direction().x()).abs() < 1e-9);
+            } else {
+                let sin_t = f64::sqrt(w.x() * w.x() + w.y() * w.y());
+                assert!((sin_t * ior - degrees_to_radian(theta).sin()).abs() < 1e-9);
+            }
+        }
+
+        let cos_theta = degrees_to_radian(theta).cos();
+        let expected = Dielectric::reflectance(cos_theta, 1.0 / ior);
+        let fraction = reflected as f64 / samples as f64;
+        assert!(
+            (fraction - expected).abs() < 0.015,
+            "reflected {} of the samples, expected {}",
+            fraction,
+            expected
+        );
+    }
 }
@@ -0,0 +1,206 @@
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::material::{shading_frame, LightReflection};
+use crate::ray::Ray;
+use crate::vec::Vec3;
+use std::f64::consts::PI;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+// Resolution of the MERL tables in theta half, theta difference and phi
+// difference. Phi difference only covers half a turn thanks to reciprocity.
+const THETA_HALF_RES: usize = 90;
+const THETA_DIFF_RES: usize = 90;
+const PHI_DIFF_RES: usize = 180;
+
+// Factors from the stored values to reflectance, per channel.
+const RED_SCALE: f64 = 1.0 / 1500.0;
+const GREEN_SCALE: f64 = 1.15 / 1500.0;
+const BLUE_SCALE: f64 = 1.66 / 1500.0;
+
+// Isotropic BRDF measured by Matusik et al. in the MERL database, tabulated
+// over the half / difference angles of Rusinkiewicz. Directions are cosine
+// sampled, the highlights being picked up by light sampling.
+pub struct Measured {
+    data: Vec<f64>,
+    // Average of the table times pi, the albedo of a diffuse surface with the
+    // same mean value.
+    albedo: Color,
+}
+
+impl Measured {
+    // Loads a `.binary` file: three little endian i32 dimensions followed by
+    // the red, green and blue tables as f64.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < 12 {
+            return Err(invalid("truncated merl header"));
+        }
+
+        let dims: Vec<i32> = bytes[..12]
+            .chunks(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let expected = [THETA_HALF_RES, THETA_DIFF_RES, PHI_DIFF_RES];
+        if dims
+            .iter()
+            .zip(expected)
+            .any(|(&d, e)| d as i64 != e as i64)
+        {
+            return Err(invalid("unexpected merl table size"));
+        }
+        let n = THETA_HALF_RES * THETA_DIFF_RES * PHI_DIFF_RES;
+        if bytes.len() < 12 + 3 * n * 8 {
+            return Err(invalid("truncated merl data"));
+        }
+
+        let data: Vec<f64> = bytes[12..12 + 3 * n * 8]
+            .chunks(8)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+            .collect();
+        let mean = |channel: usize, scale: f64| {
+            let table = &data[channel * n..(channel + 1) * n];
+            let sum: f64 = table.iter().map(|v| v.max(0.0)).sum();
+            f64::min(sum / n as f64 * scale * PI, 1.0)
+        };
+        let albedo = Color::new(
+            mean(0, RED_SCALE),
+            mean(1, GREEN_SCALE),
+            mean(2, BLUE_SCALE),
+        );
+        Ok(Self { data, albedo })
+    }
+
+    pub fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    // BRDF value for local directions, both above the surface.
+    pub fn lookup(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let half = Vec3::unit_vector(&(wo + wi));
+        let theta_half = f64::acos(half.z().clamp(-1.0, 1.0));
+        let phi_half = f64::atan2(half.y(), half.x());
+
+        // Rotate the half vector onto the normal to get the difference vector.
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let diff = rotate(&rotate(wi, &z, -phi_half), &y, -theta_half);
+        let theta_diff = f64::acos(diff.z().clamp(-1.0, 1.0));
+        let mut phi_diff = f64::atan2(diff.y(), diff.x());
+        if phi_diff < 0.0 {
+            phi_diff += PI;
+        }
+
+        // Theta half is stored non linearly to resolve sharp highlights.
+        let theta_half_index = {
+            let t = f64::sqrt(theta_half / (PI / 2.0)) * THETA_HALF_RES as f64;
+            (t as usize).min(THETA_HALF_RES - 1)
+        };
+        let theta_diff_index =
+            ((theta_diff / (PI / 2.0) * THETA_DIFF_RES as f64) as usize).min(THETA_DIFF_RES - 1);
+        let phi_diff_index = ((phi_diff / PI * PHI_DIFF_RES as f64) as usize).min(PHI_DIFF_RES - 1);
+
+        let index = phi_diff_index
+            + theta_diff_index * PHI_DIFF_RES
+            + theta_half_index * PHI_DIFF_RES * THETA_DIFF_RES;
+        let n = THETA_HALF_RES * THETA_DIFF_RES * PHI_DIFF_RES;
+
+        // Missing measurements are stored as negative values.
+        Color::new(
+            f64::max(0.0, self.data[index] * RED_SCALE),
+            f64::max(0.0, self.data[index + n] * GREEN_SCALE),
+            f64::max(0.0, self.data[index + 2 * n] * BLUE_SCALE),
+        )
+    }
+}
+
+// Rotates `v` around the unit `axis` by `angle` radians.
+fn rotate(v: &Vec3, axis: &Vec3, angle: f64) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    v * cos + axis.cross(v) * sin + axis * (axis.dot(v) * (1.0 - cos))
+}
+
+impl LightReflection for Measured {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return (false, rec.normal);
+        }
+
+        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+        if scatter_direction.near_zero() {
+            scatter_direction = rec.normal;
+        }
+        let wi = uvw.to_local(&Vec3::unit_vector(&scatter_direction));
+        if wi.z() <= 0.0 {
+            return (false, rec.normal);
+        }
+
+        // f cos / (cos / pi)
+        *attenuation = self.lookup(&wo, &wi) * PI;
+        (true, scatter_direction)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.lookup(&wo, &wi) * wi.z()
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        let cosine = rec.normal.dot(&Vec3::unit_vector(scattered));
+        f64::max(0.0, cosine / PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes a table whose red, green and blue entries hold their index.
+    fn write_table(path: &std::path::Path, dims: [i32; 3]) -> std::io::Result<()> {
+        let n = THETA_HALF_RES * THETA_DIFF_RES * PHI_DIFF_RES;
+        let mut bytes = Vec::with_capacity(12 + 3 * n * 8);
+        for d in dims {
+            bytes.extend_from_slice(&d.to_le_bytes());
+        }
+        for _ in 0..3 {
+            for i in 0..n {
+                bytes.extend_from_slice(&(i as f64).to_le_bytes());
+            }
+        }
+        fs::write(path, bytes)
+    }
+
+    #[test]
+    fn tables_are_loaded_and_looked_up() {
+        let path = std::env::temp_dir().join(format!("merl_{}.binary", std::process::id()));
+        write_table(&path, [90, 90, 180]).unwrap();
+        let measured = Measured::load(&path.to_string_lossy());
+        write_table(&path, [-90, 90, -180]).unwrap();
+        let negative = Measured::load(&path.to_string_lossy());
+        fs::remove_file(&path).unwrap();
+
+        assert!(negative.is_err());
+        let measured = measured.unwrap();
+        assert!(measured.albedo().r() > 0.0 && measured.albedo().r() <= 1.0);
+
+        // Straight reflection sits in the first bin.
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        assert_eq!(measured.lookup(&z, &z).r(), 0.0);
+
+        // Mirrored directions 46 degrees off the normal: the half vector is the
+        // normal, and the difference angle falls in bin 46.
+        let theta = 46.5_f64.to_radians();
+        let wo = Vec3::new(-theta.sin(), 0.0, theta.cos());
+        let wi = Vec3::new(theta.sin(), 0.0, theta.cos());
+        let index = (46 * PHI_DIFF_RES) as f64;
+        let c = measured.lookup(&wo, &wi);
+        assert!((c.r() - index * RED_SCALE).abs() < 1e-9);
+        assert!((c.b() - index * BLUE_SCALE).abs() < 1e-9);
+    }
+}
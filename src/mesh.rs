@@ -0,0 +1,196 @@
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{Lambertian, MaterialType};
+use crate::ray::Ray;
+use crate::triangle::Triangle;
+use crate::vec::Vec3;
+use std::rc::Rc;
+
+// Leaves hold at most this many triangles.
+const MAX_LEAF_SIZE: usize = 4;
+
+// Axis aligned bounding box.
+#[derive(Clone, Copy)]
+struct Bounds {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Bounds {
+    fn of_triangle(t: &Triangle) -> Self {
+        let min = |a: f64, b: f64, c: f64| a.min(b).min(c);
+        let max = |a: f64, b: f64, c: f64| a.max(b).max(c);
+        Self {
+            min: Vec3::new(
+                min(t.v0.x(), t.v1.x(), t.v2.x()),
+                min(t.v0.y(), t.v1.y(), t.v2.y()),
+                min(t.v0.z(), t.v1.z(), t.v2.z()),
+            ),
+            max: Vec3::new(
+                max(t.v0.x(), t.v1.x(), t.v2.x()),
+                max(t.v0.y(), t.v1.y(), t.v2.y()),
+                max(t.v0.z(), t.v1.z(), t.v2.z()),
+            ),
+        }
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        Self {
+            min: Vec3::new(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            max: Vec3::new(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        }
+    }
+
+    fn centroid(&self, axis: usize) -> f64 {
+        (component(&self.min, axis) + component(&self.max, axis)) / 2.0
+    }
+
+    // Slab test against the ray, `inv_dir` holding the inverse direction.
+    fn hit(&self, origin: &Vec3, inv_dir: &Vec3, t_min: f64, t_max: f64) -> bool {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for axis in 0..3 {
+            let inv = component(inv_dir, axis);
+            let mut near = (component(&self.min, axis) - component(origin, axis)) * inv;
+            let mut far = (component(&self.max, axis) - component(origin, axis)) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t1 < t0 {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn component(v: &Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x(),
+        1 => v.y(),
+        _ => v.z(),
+    }
+}
+
+// Interior nodes point at their two children, leaves at a range of triangles.
+struct Node {
+    bounds: Bounds,
+    first: usize,
+    count: usize,
+}
+
+// Triangle mesh with a bounding volume hierarchy, so large imported models
+// don't have to test every triangle for every ray.
+pub struct Mesh {
+    pub triangles: Vec<Triangle>,
+    nodes: Vec<Node>,
+}
+
+impl Mesh {
+    pub fn new(mut triangles: Vec<Triangle>) -> Self {
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            let count = triangles.len();
+            build(&mut nodes, &mut triangles, 0, count);
+        }
+        Self { triangles, nodes }
+    }
+}
+
+// Builds the node covering `triangles[start..end]` and returns its index,
+// splitting at the median centroid along the widest axis.
+fn build(nodes: &mut Vec<Node>, triangles: &mut [Triangle], start: usize, end: usize) -> usize {
+    let bounds = triangles[start..end]
+        .iter()
+        .map(Bounds::of_triangle)
+        .reduce(|a, b| a.union(&b))
+        .expect("empty bvh node");
+
+    let index = nodes.len();
+    nodes.push(Node {
+        bounds,
+        first: start,
+        count: end - start,
+    });
+    if end - start <= MAX_LEAF_SIZE {
+        return index;
+    }
+
+    let extent = bounds.max - bounds.min;
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+        0
+    } else if extent.y() > extent.z() {
+        1
+    } else {
+        2
+    };
+    triangles[start..end].sort_by(|a, b| {
+        let ca = Bounds::of_triangle(a).centroid(axis);
+        let cb = Bounds::of_triangle(b).centroid(axis);
+        ca.total_cmp(&cb)
+    });
+
+    let mid = (start + end) / 2;
+    let left = build(nodes, triangles, start, mid);
+    let right = build(nodes, triangles, mid, end);
+    debug_assert_eq!(left, index + 1);
+    nodes[index].first = right;
+    nodes[index].count = 0;
+    index
+}
+
+impl Hittable for Mesh {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+    ) -> (bool, Rc<MaterialType>) {
+        let mut material = match self.triangles.first() {
+            Some(t) => Rc::clone(&t.m),
+            None => {
+                let m = MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+                return (false, Rc::new(m));
+            }
+        };
+
+        let dir = r.direction();
+        let inv_dir = Vec3::new(1.0 / dir.x(), 1.0 / dir.y(), 1.0 / dir.z());
+        let mut hit_anything = false;
+        let mut closest_so_far = t_max;
+
+        // The left child of an interior node directly follows it.
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds.hit(r.origin(), &inv_dir, t_min, closest_so_far) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.first);
+                stack.push(index + 1);
+                continue;
+            }
+            for triangle in &self.triangles[node.first..node.first + node.count] {
+                let (is_hit, m) = triangle.hit(r, t_min, closest_so_far, rec);
+                if is_hit {
+                    hit_anything = true;
+                    material = m;
+                    closest_so_far = rec.t;
+                }
+            }
+        }
+
+        (hit_anything, material)
+    }
+}
@@ -0,0 +1,127 @@
+use crate::vec::Vec3;
+use std::f64::consts::PI;
+
+// Below this roughness the distribution is treated as a perfect mirror.
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+// Trowbridge-Reitz (GGX) microfacet distribution. All directions are in the
+// local shading frame where the surface normal is +z.
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    // Maps a perceptual roughness in [0, 1] to the distribution alpha.
+    pub fn roughness_to_alpha(roughness: f64) -> f64 {
+        roughness * roughness
+    }
+
+    // Distribution with separate roughness along the tangent (x) and the
+    // bitangent (y). A mirror-like axis is kept just above the smooth limit
+    // when the other one is rough, so the lobe stays finite.
+    pub fn anisotropic(roughness_x: f64, roughness_y: f64) -> Self {
+        let alpha_x = Self::roughness_to_alpha(roughness_x);
+        let alpha_y = Self::roughness_to_alpha(roughness_y);
+        if f64::max(alpha_x, alpha_y) < SMOOTH_ALPHA {
+            return Self::new(alpha_x, alpha_y);
+        }
+        Self::new(
+            f64::max(alpha_x, SMOOTH_ALPHA),
+            f64::max(alpha_y, SMOOTH_ALPHA),
+        )
+    }
+
+    // The same distribution kept just above the smooth limit, for surfaces
+    // whose roughness varies and must not turn specular at some hits only.
+    pub fn at_least_rough(self) -> Self {
+        Self::new(
+            f64::max(self.alpha_x, SMOOTH_ALPHA),
+            f64::max(self.alpha_y, SMOOTH_ALPHA),
+        )
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        f64::max(self.alpha_x, self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2_theta = wm.z() * wm.z();
+        if cos2_theta == 0.0 {
+            return 0.0;
+        }
+        let e = (wm.x() * wm.x() / (self.alpha_x * self.alpha_x)
+            + wm.y() * wm.y() / (self.alpha_y * self.alpha_y))
+            / cos2_theta;
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos2_theta * cos2_theta * (1.0 + e) * (1.0 + e))
+    }
+
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2_theta = w.z() * w.z();
+        if cos2_theta == 0.0 {
+            return 0.0;
+        }
+        let alpha2_tan2_theta = (w.x() * w.x() * self.alpha_x * self.alpha_x
+            + w.y() * w.y() * self.alpha_y * self.alpha_y)
+            / cos2_theta;
+        (f64::sqrt(1.0 + alpha2_tan2_theta) - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Distribution of normals visible from direction `w`.
+    pub fn d_visible(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        if w.z() == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z().abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    // Samples a visible normal as seen from `w`, following Heitz, "Sampling
+    // the GGX Distribution of Visible Normals" (2018).
+    pub fn sample_wm(&self, w: &Vec3, u1: f64, u2: f64) -> Vec3 {
+        let mut wh = Vec3::unit_vector(&Vec3::new(
+            self.alpha_x * w.x(),
+            self.alpha_y * w.y(),
+            w.z(),
+        ));
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+
+        let t1 = if wh.z() < 0.99999 {
+            Vec3::unit_vector(&Vec3::new(0.0, 0.0, 1.0).cross(&wh))
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // Uniform point on the disk, warped towards the visible half.
+        let r = f64::sqrt(u1);
+        let phi = 2.0 * PI * u2;
+        let px = r * f64::cos(phi);
+        let mut py = r * f64::sin(phi);
+        let h = f64::sqrt(1.0 - px * px);
+        let s = (1.0 + wh.z()) / 2.0;
+        py = (1.0 - s) * h + s * py;
+        let pz = f64::sqrt(f64::max(0.0, 1.0 - px * px - py * py));
+
+        let nh = t1 * px + t2 * py + wh * pz;
+        Vec3::unit_vector(&Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            f64::max(1e-6, nh.z()),
+        ))
+    }
+}
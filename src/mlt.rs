@@ -0,0 +1,131 @@
+use crate::color::Color;
+use crate::distribution::Distribution1D;
+use crate::sampler::{self, MltSampler, Sampler, SharedMltSampler};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Independent paths traced up front to estimate the image brightness and to
+// pick the starting paths of the chains.
+const BOOTSTRAP_SAMPLES: usize = 100_000;
+
+// Markov chains sharing the mutation budget.
+const CHAINS: usize = 256;
+
+// Standard deviation of small steps and share of large steps.
+const SIGMA: f64 = 0.01;
+const LARGE_STEP_PROBABILITY: f64 = 0.3;
+
+// Path contribution landing on one pixel.
+struct Splat {
+    pixel: usize,
+    color: Color,
+    // Scalar contribution the chains sample proportionally to.
+    importance: f64,
+}
+
+// Primary sample space Metropolis light transport (Kelemen et al. 2002).
+// Chains of mutated paths visit the image in proportion to their brightness,
+// which finds light through narrow openings that independent paths rarely
+// hit. `sample` maps camera coordinates to radiance, drawing its decisions
+// from the sampler it is given. Returns the pixels top row first, averaged
+// like a regular render of `samples_per_pixel` paths per pixel.
+pub fn render(
+    width: u32,
+    height: u32,
+    samples_per_pixel: u32,
+    sample: impl Fn(f64, f64, &mut dyn Sampler) -> Color,
+) -> Vec<Color> {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let pixel_count = (width * height) as usize;
+    let mut image = vec![black; pixel_count];
+
+    // The normalization constant is the mean importance over primary sample
+    // space. Seeding by index lets a chain replay its bootstrap path.
+    let weights: Vec<f64> = (0..BOOTSTRAP_SAMPLES)
+        .map(|i| trace(&seeded(i), width, height, &sample).importance)
+        .collect();
+    let b = weights.iter().sum::<f64>() / BOOTSTRAP_SAMPLES as f64;
+    if b == 0.0 {
+        return image;
+    }
+    let bootstrap = Distribution1D::new(weights);
+
+    let mutations = pixel_count * samples_per_pixel as usize / CHAINS;
+    for _ in 0..CHAINS {
+        let (index, _) = bootstrap.sample_discrete();
+        let sampler = seeded(index);
+        let mut current = trace(&sampler, width, height, &sample);
+
+        for _ in 0..mutations {
+            sampler.borrow_mut().start_iteration();
+            let proposed = trace(&sampler, width, height, &sample);
+            let accept = f64::min(1.0, proposed.importance / current.importance);
+
+            // Both paths contribute by their expected share of the next state.
+            if accept > 0.0 {
+                image[proposed.pixel] += proposed.color * (accept / proposed.importance);
+            }
+            image[current.pixel] += current.color * ((1.0 - accept) / current.importance);
+
+            let mut sampler = sampler.borrow_mut();
+            if sampler.uniform() < accept {
+                sampler.accept();
+                current = proposed;
+            } else {
+                sampler.reject();
+            }
+        }
+    }
+
+    let scale = b * pixel_count as f64 / (mutations * CHAINS) as f64;
+    for pixel in image.iter_mut() {
+        *pixel *= scale;
+    }
+    image
+}
+
+fn seeded(index: usize) -> SharedMltSampler {
+    Rc::new(RefCell::new(MltSampler::new(
+        index as u64,
+        SIGMA,
+        LARGE_STEP_PROBABILITY,
+    )))
+}
+
+// Traces the path encoded by the sampler's current primary samples, the
+// first two of which pick the point on the image.
+fn trace(
+    sampler: &SharedMltSampler,
+    width: u32,
+    height: u32,
+    sample: &impl Fn(f64, f64, &mut dyn Sampler) -> Color,
+) -> Splat {
+    sampler::drive(sampler, || {
+        let mut sampler = Rc::clone(sampler);
+        let x = sampler.get_1d() * width as f64;
+        let y = sampler.get_1d() * height as f64;
+        let color = sample(
+            x / (width as f64 - 1.0),
+            y / (height as f64 - 1.0),
+            &mut sampler,
+        );
+
+        let i = usize::min(x as usize, width as usize - 1);
+        let j = usize::min(y as usize, height as usize - 1);
+        let row = height as usize - 1 - j;
+
+        // Spectral samples can have negative components, and a stray NaN
+        // must not stall a chain.
+        let importance = color.luminance();
+        let importance = if importance.is_finite() {
+            importance.max(0.0)
+        } else {
+            0.0
+        };
+        Splat {
+            pixel: row * width as usize + i,
+            color,
+            importance,
+        }
+    })
+}
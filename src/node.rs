@@ -0,0 +1,218 @@
+use crate::color::Color;
+use crate::fresnel::fresnel_dielectric;
+use crate::hittable::HitRecord;
+use crate::ray::Ray;
+use crate::texture::ImageTexture;
+use crate::vec::Vec3;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::rc::Rc;
+
+// Shading node, evaluated at every hit to drive material parameters. Nodes
+// reference their inputs through `Rc`, so a graph can share sub-expressions.
+// Scalar inputs, like a mix factor, read the luminance of their node.
+pub enum Node {
+    Constant(Color),
+    // Image lookup at the hit's (u, v), repeated `tiling` times.
+    Texture {
+        image: Rc<ImageTexture>,
+        tiling: f64,
+    },
+    // Linear blend from `a` to `b`.
+    Mix {
+        a: Rc<Node>,
+        b: Rc<Node>,
+        factor: Rc<Node>,
+    },
+    Multiply(Rc<Node>, Rc<Node>),
+    // Fractal Perlin noise of the hit position, in [0, 1].
+    Noise(Perlin),
+    // Dielectric reflectance for the viewing angle, brighter at grazing angles.
+    Fresnel {
+        ior: f64,
+    },
+    // Color gradient indexed by the input, stops sorted by position.
+    Ramp {
+        input: Rc<Node>,
+        stops: Vec<(f64, Color)>,
+    },
+}
+
+impl Node {
+    pub fn constant(color: Color) -> Rc<Self> {
+        Rc::new(Node::Constant(color))
+    }
+
+    // Constant for a scalar input.
+    pub fn uniform(value: f64) -> Rc<Self> {
+        Self::constant(Color::new(value, value, value))
+    }
+
+    // Value of a node that is the same at every hit.
+    pub fn as_constant(&self) -> Option<Color> {
+        match self {
+            Node::Constant(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    pub fn eval_scalar(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        self.eval(r_in, rec).luminance()
+    }
+
+    pub fn eval(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        match self {
+            Node::Constant(c) => *c,
+            Node::Texture { image, tiling } => image.value(rec.u * tiling, rec.v * tiling),
+            Node::Mix { a, b, factor } => {
+                let t = factor.eval(r_in, rec).luminance().clamp(0.0, 1.0);
+                a.eval(r_in, rec) * (1.0 - t) + b.eval(r_in, rec) * t
+            }
+            Node::Multiply(a, b) => a.eval(r_in, rec) * b.eval(r_in, rec),
+            Node::Noise(perlin) => {
+                let n = perlin.value(&rec.p);
+                Color::new(n, n, n)
+            }
+            Node::Fresnel { ior } => {
+                let cos_theta = -rec.normal.dot(&Vec3::unit_vector(r_in.direction()));
+                let f = fresnel_dielectric(cos_theta.clamp(0.0, 1.0), *ior);
+                Color::new(f, f, f)
+            }
+            Node::Ramp { input, stops } => ramp(stops, input.eval(r_in, rec).luminance()),
+        }
+    }
+
+    // Rough average over the surface, for code that needs a single color such
+    // as importance sampling heuristics.
+    pub fn mean(&self) -> Color {
+        match self {
+            Node::Constant(c) => *c,
+            Node::Texture { image, .. } => image.mean(),
+            Node::Mix { a, b, factor } => {
+                let t = factor.mean().luminance().clamp(0.0, 1.0);
+                a.mean() * (1.0 - t) + b.mean() * t
+            }
+            Node::Multiply(a, b) => a.mean() * b.mean(),
+            Node::Noise(_) => Color::new(0.5, 0.5, 0.5),
+            Node::Fresnel { ior } => {
+                let f = fresnel_dielectric(1.0, *ior);
+                Color::new(f, f, f)
+            }
+            Node::Ramp { input, stops } => ramp(stops, input.mean().luminance()),
+        }
+    }
+}
+
+fn ramp(stops: &[(f64, Color)], t: f64) -> Color {
+    let (first, last) = match (stops.first(), stops.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Color::new(0.0, 0.0, 0.0),
+    };
+    if t <= first.0 {
+        return first.1;
+    }
+    for pair in stops.windows(2) {
+        let ((p0, c0), (p1, c1)) = (pair[0], pair[1]);
+        if t <= p1 {
+            let s = if p1 > p0 { (t - p0) / (p1 - p0) } else { 1.0 };
+            return c0 * (1.0 - s) + c1 * s;
+        }
+    }
+    last.1
+}
+
+// Improved Perlin noise (Perlin 2002) summed over octaves.
+pub struct Perlin {
+    pub scale: f64,
+    pub octaves: u32,
+    permutation: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(scale: f64, octaves: u32) -> Self {
+        // A fixed seed keeps renders reproducible.
+        let mut permutation: Vec<usize> = (0..256).collect();
+        permutation.shuffle(&mut StdRng::seed_from_u64(0x5eed));
+        permutation.extend_from_within(..);
+        Self {
+            scale,
+            octaves: octaves.max(1),
+            permutation,
+        }
+    }
+
+    pub fn value(&self, p: &Vec3) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        let mut frequency = self.scale;
+        for _ in 0..self.octaves {
+            sum += amplitude * self.noise(p.x() * frequency, p.y() * frequency, p.z() * frequency);
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        (0.5 + 0.5 * sum / total).clamp(0.0, 1.0)
+    }
+
+    // Gradient noise in [-1, 1].
+    fn noise(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (xf, yf, zf) = (x.floor(), y.floor(), z.floor());
+        let (xi, yi, zi) = (
+            (xf as i64 & 255) as usize,
+            (yf as i64 & 255) as usize,
+            (zf as i64 & 255) as usize,
+        );
+        let (x, y, z) = (x - xf, y - yf, z - zf);
+        let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+        let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+
+        let p = &self.permutation;
+        let a = p[xi] + yi;
+        let (aa, ab) = (p[a] + zi, p[a + 1] + zi);
+        let b = p[xi + 1] + yi;
+        let (ba, bb) = (p[b] + zi, p[b + 1] + zi);
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p[aa], x, y, z), grad(p[ba], x - 1.0, y, z)),
+                lerp(
+                    u,
+                    grad(p[ab], x, y - 1.0, z),
+                    grad(p[bb], x - 1.0, y - 1.0, z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(p[aa + 1], x, y, z - 1.0),
+                    grad(p[ba + 1], x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(p[ab + 1], x, y - 1.0, z - 1.0),
+                    grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+}
+
+// Dot product with one of the twelve edge gradients of a cube.
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
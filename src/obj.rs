@@ -0,0 +1,491 @@
+// Wavefront .obj meshes and their .mtl material libraries.
+//
+// Faces are triangulated as fans. Each `usemtl` material is mapped to the
+// closest material of the renderer:
+//
+//   Ke            emission, as a diffuse light whose triangles are area lights
+//   illum 3, 5    conductor reflecting Ks, with a GGX roughness from Ns
+//   illum 4, 6, 7, 9
+//                 glass with index of refraction Ni
+//   Ks with Ns    diffuse Kd under a glossy dielectric coat, blended in by
+//                 the luminance of Ks
+//   otherwise     diffuse Kd
+//
+// `map_Kd` textures the diffuse color, `map_Bump` / `bump` and `norm` add
+// height and normal maps, `d` and `map_d` cut the surface out. Other maps are
+// ignored.
+use crate::bump::Bumped;
+use crate::color::Color;
+use crate::layered::Layered;
+use crate::material::{Conductor, Dielectric, DiffuseLight, Lambertian, Masked, MaterialType, Mix};
+use crate::mesh::Mesh;
+use crate::node::Node;
+use crate::texture::ImageTexture;
+use crate::triangle::Triangle;
+use crate::vec::Vec3;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::rc::Rc;
+
+// Loads a mesh, `transform` placing its vertices in the scene. Faces use the
+// materials of the .mtl libraries the file references, or `fallback` when
+// they have none or `fallback` overrides them.
+pub fn load(
+    path: &str,
+    transform: impl Fn(&Vec3) -> Vec3,
+    fallback: Rc<MaterialType>,
+    override_materials: bool,
+) -> std::io::Result<Mesh> {
+    let source = fs::read_to_string(path)?;
+    let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut materials: HashMap<String, Rc<MaterialType>> = HashMap::new();
+    let mut current = Rc::clone(&fallback);
+    let mut triangles = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let words: Vec<&str> = line
+            .split('#')
+            .next()
+            .unwrap_or("")
+            .split_whitespace()
+            .collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        match words[0] {
+            "v" => positions.push(transform(&vector(&words[1..], line_number)?)),
+            "vn" => {
+                // Normals follow the transform's linear part.
+                let origin = transform(&Vec3::new(0.0, 0.0, 0.0));
+                let n = transform(&vector(&words[1..], line_number)?) - origin;
+                normals.push(Vec3::unit_vector(&n));
+            }
+            "vt" => {
+                let u = number(words.get(1), line_number)?;
+                let v = number(words.get(2).or(Some(&"0")), line_number)?;
+                uvs.push((u, v));
+            }
+            "mtllib" if !override_materials => {
+                for file in &words[1..] {
+                    let library = directory.join(file);
+                    materials.extend(load_mtl(&library, directory)?);
+                }
+            }
+            "usemtl" if !override_materials => {
+                current = words
+                    .get(1)
+                    .and_then(|name| materials.get(*name))
+                    .cloned()
+                    .unwrap_or_else(|| Rc::clone(&fallback));
+            }
+            "f" => {
+                let corners = words[1..]
+                    .iter()
+                    .map(|corner| {
+                        parse_corner(
+                            corner,
+                            positions.len(),
+                            uvs.len(),
+                            normals.len(),
+                            line_number,
+                        )
+                    })
+                    .collect::<std::io::Result<Vec<_>>>()?;
+                if corners.len() < 3 {
+                    return Err(invalid(line_number, "face with less than three vertices"));
+                }
+
+                for i in 1..corners.len() - 1 {
+                    let [a, b, c] = [corners[0], corners[i], corners[i + 1]];
+                    // Emissive triangles become lights, each of which must
+                    // own its material.
+                    let m = match current.as_ref() {
+                        MaterialType::DiffuseLight(l) => {
+                            Rc::new(MaterialType::DiffuseLight(DiffuseLight::new(l.emit)))
+                        }
+                        _ => Rc::clone(&current),
+                    };
+                    let mut triangle =
+                        Triangle::new(positions[a.0], positions[b.0], positions[c.0], m);
+                    if let (Some(ta), Some(tb), Some(tc)) = (a.1, b.1, c.1) {
+                        triangle = triangle.with_uvs([uvs[ta], uvs[tb], uvs[tc]]);
+                    }
+                    if let (Some(na), Some(nb), Some(nc)) = (a.2, b.2, c.2) {
+                        triangle = triangle.with_normals([normals[na], normals[nb], normals[nc]]);
+                    }
+                    triangles.push(triangle);
+                }
+            }
+            // Groups, objects, smoothing groups and the rest don't matter here.
+            _ => {}
+        }
+    }
+
+    if triangles.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "obj file without faces"));
+    }
+    Ok(Mesh::new(triangles))
+}
+
+// Zero based position, texture coordinate and normal indices of a face corner
+// written `v`, `v/vt`, `v//vn` or `v/vt/vn`. Negative indices count back from
+// the last element read.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+fn parse_corner(
+    corner: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+    line: usize,
+) -> std::io::Result<Corner> {
+    let resolve = |field: Option<&str>, count: usize| -> std::io::Result<Option<usize>> {
+        let field = match field {
+            Some(f) if !f.is_empty() => f,
+            _ => return Ok(None),
+        };
+        let index: i64 = field
+            .parse()
+            .map_err(|_| invalid(line, &format!("bad index {}", field)))?;
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(invalid(line, &format!("index {} out of range", index)));
+        }
+        Ok(Some(resolved as usize))
+    };
+
+    let mut fields = corner.split('/');
+    let position = resolve(fields.next(), positions)?
+        .ok_or_else(|| invalid(line, "face corner without a position"))?;
+    let uv = resolve(fields.next(), uvs)?;
+    let normal = resolve(fields.next(), normals)?;
+    Ok((position, uv, normal))
+}
+
+// Material statements of a .mtl library, converted once the whole entry has
+// been read.
+#[derive(Default)]
+struct MtlEntry {
+    kd: Option<Color>,
+    ks: Option<Color>,
+    ke: Option<Color>,
+    ns: Option<f64>,
+    ni: Option<f64>,
+    d: Option<f64>,
+    illum: Option<u32>,
+    map_kd: Option<String>,
+    map_d: Option<String>,
+    bump: Option<(String, f64)>,
+    norm: Option<String>,
+}
+
+fn load_mtl(path: &Path, directory: &Path) -> std::io::Result<HashMap<String, Rc<MaterialType>>> {
+    let source = fs::read_to_string(path)?;
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let words: Vec<&str> = line
+            .split('#')
+            .next()
+            .unwrap_or("")
+            .split_whitespace()
+            .collect();
+        if words.is_empty() {
+            continue;
+        }
+        if words[0] == "newmtl" {
+            let name = words.get(1).unwrap_or(&"").to_string();
+            entries.push((name, MtlEntry::default()));
+            continue;
+        }
+        let entry = match entries.last_mut() {
+            Some((_, entry)) => entry,
+            None => continue,
+        };
+
+        let args = &words[1..];
+        match words[0] {
+            "Kd" => entry.kd = Some(color(args, line_number)?),
+            "Ks" => entry.ks = Some(color(args, line_number)?),
+            "Ke" => entry.ke = Some(color(args, line_number)?),
+            "Ns" => entry.ns = Some(number(args.first(), line_number)?),
+            "Ni" => entry.ni = Some(number(args.first(), line_number)?),
+            "d" => entry.d = Some(number(args.last(), line_number)?),
+            "Tr" => entry.d = Some(1.0 - number(args.last(), line_number)?),
+            "illum" => entry.illum = Some(number(args.first(), line_number)? as u32),
+            "map_Kd" => entry.map_kd = map_file(args),
+            "map_d" => entry.map_d = map_file(args),
+            "map_Bump" | "map_bump" | "bump" => {
+                // `-bm` scales the bump heights.
+                let strength = args
+                    .iter()
+                    .position(|a| *a == "-bm")
+                    .and_then(|i| args.get(i + 1))
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(1.0);
+                entry.bump = map_file(args).map(|file| (file, strength));
+            }
+            "norm" | "map_Norm" => entry.norm = map_file(args),
+            _ => {}
+        }
+    }
+
+    let mut textures: HashMap<String, Rc<ImageTexture>> = HashMap::new();
+    let mut texture = |file: &str, srgb: bool| -> std::io::Result<Rc<ImageTexture>> {
+        let key = format!("{}:{}", file, srgb);
+        if let Some(t) = textures.get(&key) {
+            return Ok(Rc::clone(t));
+        }
+        let path = directory.join(file);
+        let mut image = ImageTexture::load(&path.to_string_lossy())?;
+        if srgb {
+            image = image.srgb_to_linear();
+        }
+        let image = Rc::new(image);
+        textures.insert(key, Rc::clone(&image));
+        Ok(image)
+    };
+
+    let mut materials = HashMap::new();
+    for (name, entry) in entries {
+        let mut material = convert(&entry, &mut texture)?;
+        // Emitters stay plain diffuse lights so their triangles are sampled.
+        if let MaterialType::DiffuseLight(_) = material {
+            materials.insert(name, Rc::new(material));
+            continue;
+        }
+        if let Some((file, strength)) = &entry.bump {
+            let base = Rc::new(material);
+            // Heights span the whole texture range, scaled down to fine detail.
+            material = MaterialType::Bumped(
+                Bumped::height_map(base, texture(file, false)?).with_strength(strength * 0.01),
+            );
+        }
+        if let Some(file) = &entry.norm {
+            let base = Rc::new(material);
+            material = MaterialType::Bumped(Bumped::normal_map(base, texture(file, false)?));
+        }
+        let transparent = matches!(entry.illum, Some(4 | 6 | 7 | 9));
+        if let Some(file) = &entry.map_d {
+            let base = Rc::new(material);
+            material = MaterialType::Masked(Masked::new(base, texture(file, false)?));
+        } else if let Some(d) = entry.d.filter(|d| *d < 1.0 && !transparent) {
+            let base = Rc::new(material);
+            let opacity = Rc::new(ImageTexture::constant(Color::new(d, d, d)));
+            material = MaterialType::Masked(Masked::new(base, opacity));
+        }
+        materials.insert(name, Rc::new(material));
+    }
+    Ok(materials)
+}
+
+// Closest material for the reflection model of an .mtl entry.
+fn convert(
+    entry: &MtlEntry,
+    texture: &mut impl FnMut(&str, bool) -> std::io::Result<Rc<ImageTexture>>,
+) -> std::io::Result<MaterialType> {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let kd = entry.kd.unwrap_or(Color::new(0.8, 0.8, 0.8));
+    let ks = entry.ks.unwrap_or(black);
+    let ior = entry.ni.filter(|n| *n >= 1.0).unwrap_or(1.5);
+    // Blinn-Phong exponent to a microfacet roughness.
+    let roughness = entry
+        .ns
+        .map(|ns| f64::sqrt(f64::sqrt(2.0 / (ns.max(0.0) + 2.0))))
+        .unwrap_or(0.5);
+
+    if let Some(ke) = entry.ke.filter(|ke| !ke.is_black()) {
+        return Ok(MaterialType::DiffuseLight(DiffuseLight::new(ke)));
+    }
+
+    let material = match entry.illum {
+        Some(3 | 5) => MaterialType::Conductor(Conductor::from_reflectance(ks, roughness)),
+        Some(4 | 6 | 7 | 9) => MaterialType::Dielectric(Dielectric::new(ior)),
+        _ => {
+            let mut diffuse = Lambertian::new(kd);
+            if let Some(file) = &entry.map_kd {
+                // Texture colors replace Kd, unless it was given as a tint.
+                let tint = Node::constant(entry.kd.unwrap_or(Color::new(1.0, 1.0, 1.0)));
+                let image = Rc::new(Node::Texture {
+                    image: texture(file, true)?,
+                    tiling: 1.0,
+                });
+                diffuse = diffuse.with_albedo(Rc::new(Node::Multiply(tint, image)));
+            }
+            let diffuse = MaterialType::Lambertian(diffuse);
+            // The coat is blended in by the strength of Ks. Being a clear
+            // dielectric, it can't carry the hue of Ks.
+            let coat = ks.luminance().clamp(0.0, 1.0);
+            if entry.illum == Some(1) || coat < 0.01 {
+                return Ok(diffuse);
+            }
+            let diffuse = Rc::new(diffuse);
+            let layered = MaterialType::Layered(Layered::new(Rc::clone(&diffuse), ior, roughness));
+            if coat > 0.99 {
+                layered
+            } else {
+                MaterialType::Mix(Mix::new(diffuse, Rc::new(layered), Node::constant(ks)))
+            }
+        }
+    };
+    Ok(material)
+}
+
+// The file name ends the statement, after any options.
+fn map_file(args: &[&str]) -> Option<String> {
+    args.last().map(|s| s.to_string())
+}
+
+fn invalid(line: usize, msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line, msg))
+}
+
+fn number(word: Option<&&str>, line: usize) -> std::io::Result<f64> {
+    word.and_then(|w| w.parse().ok())
+        .ok_or_else(|| invalid(line, "expected a number"))
+}
+
+fn vector(args: &[&str], line: usize) -> std::io::Result<Vec3> {
+    Ok(Vec3::new(
+        number(args.first(), line)?,
+        number(args.get(1), line)?,
+        number(args.get(2), line)?,
+    ))
+}
+
+// A single value stands for a grey.
+fn color(args: &[&str], line: usize) -> std::io::Result<Color> {
+    let r = number(args.first(), line)?;
+    if args.len() < 3 {
+        return Ok(Color::new(r, r, r));
+    }
+    Ok(Color::new(
+        r,
+        number(args.get(1), line)?,
+        number(args.get(2), line)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HitRecord;
+    use crate::ray::Ray;
+    use std::path::PathBuf;
+
+    // Writes `files` to a fresh directory and loads the first one as a mesh.
+    fn load_files(test: &str, files: &[(&str, &str)]) -> std::io::Result<Mesh> {
+        let directory: PathBuf =
+            std::env::temp_dir().join(format!("obj_{}_{}", test, std::process::id()));
+        fs::create_dir_all(&directory)?;
+        for (name, contents) in files {
+            fs::write(directory.join(name), contents)?;
+        }
+        let grey = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mesh = load(
+            &directory.join(files[0].0).to_string_lossy(),
+            |p| *p,
+            Rc::new(MaterialType::Lambertian(grey)),
+            false,
+        );
+        fs::remove_dir_all(&directory)?;
+        mesh
+    }
+
+    fn albedo(m: &MaterialType) -> Color {
+        let (origin, dir) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        m.albedo_at(&Ray::new(&origin, &dir), &HitRecord::new())
+    }
+
+    #[test]
+    fn faces_are_fans_with_relative_indices() {
+        let source = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 1\n\
+                      f 1/1 2/2 3/2 4/1\nv 5 5 5\nf -1 -2 -3\n";
+        let mesh = load_files("faces", &[("quad.obj", source)]).unwrap();
+        assert_eq!(mesh.triangles.len(), 3);
+
+        let mut corners: Vec<_> = mesh
+            .triangles
+            .iter()
+            .map(|t| (t.v0.x(), t.v1.x(), t.v2.x(), t.v2.y()))
+            .collect();
+        corners.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        // Fan around the first corner of the quad, then the last three
+        // vertices backwards.
+        assert_eq!(corners[0], (0.0, 1.0, 0.0, 1.0));
+        assert_eq!(corners[1], (0.0, 1.0, 1.0, 1.0));
+        assert_eq!(corners[2], (5.0, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn bad_faces_report_their_line() {
+        let error = |source: &str| load_files("errors", &[("bad.obj", source)]).err().unwrap();
+        let message = error("v 0 0 0\nv 1 0 0\nf 1 2\n").to_string();
+        assert_eq!(message, "line 3: face with less than three vertices");
+        let message = error("v 0 0 0\nv 1 0 0\nv 1 1 0\n\nf 1 2 -4\n").to_string();
+        assert_eq!(message, "line 5: index -4 out of range");
+        let message = error("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3/1\n").to_string();
+        assert_eq!(message, "line 4: index 1 out of range");
+    }
+
+    #[test]
+    fn mtl_materials_are_converted() {
+        let obj = "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\n\
+                   usemtl textured\nf 1 2 3\nusemtl chrome\nf 1 2 3\n\
+                   usemtl satin\nf 1 2 3\nusemtl lamp\nf 1 2 3\nf 3 2 1\n\
+                   usemtl unknown\nf 1 2 3\n";
+        let mtl = "newmtl textured\nKd 0.5 0.5 0.5\nmap_Kd -s 1 1 1 magenta.ppm\n\
+                   newmtl chrome\nKs 0.9 0.9 0.9\nNs 100\nillum 3\n\
+                   newmtl satin\nKd 0.2 0.2 0.2\nKs 0.2 0.2 0.2\nillum 2\n\
+                   newmtl lamp\nKe 4 4 4\n";
+        let mesh = load_files(
+            "mtl",
+            &[
+                ("scene.obj", obj),
+                ("scene.mtl", mtl),
+                ("magenta.ppm", "P3 1 1 255\n255 0 255\n"),
+            ],
+        )
+        .unwrap();
+
+        let materials: Vec<_> = mesh.triangles.iter().map(|t| Rc::clone(&t.m)).collect();
+        let textured = materials
+            .iter()
+            .find(|m| matches!(m.as_ref(), MaterialType::Lambertian(_)) && albedo(m).g() == 0.0)
+            .expect("textured material");
+        let c = albedo(textured);
+        assert!((c.r() - 0.5).abs() < 1e-9 && (c.b() - 0.5).abs() < 1e-9);
+
+        assert!(materials
+            .iter()
+            .any(|m| matches!(m.as_ref(), MaterialType::Conductor(_))));
+        assert!(materials
+            .iter()
+            .any(|m| matches!(m.as_ref(), MaterialType::Mix(_))));
+        // Unknown names fall back to the default material.
+        assert!(materials
+            .iter()
+            .any(|m| matches!(m.as_ref(), MaterialType::Lambertian(_)) && albedo(m).g() == 0.5));
+
+        // Every emissive triangle owns its material, so lights can tell them apart.
+        let lamps: Vec<_> = materials
+            .iter()
+            .filter(|m| matches!(m.as_ref(), MaterialType::DiffuseLight(_)))
+            .collect();
+        assert_eq!(lamps.len(), 2);
+        assert!(!Rc::ptr_eq(lamps[0], lamps[1]));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::vec::Vec3;
+
+// Orthonormal basis built around a single direction, used to move sampled
+// directions between local (w = up) and world space.
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn build_from_w(n: &Vec3) -> Self {
+        let w = Vec3::unit_vector(n);
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::unit_vector(&w.cross(&a));
+        let u = w.cross(&v);
+
+        Self { u, v, w }
+    }
+
+    // Basis around `n` with `u` aligned to the projection of `tangent`, so
+    // anisotropic lobes follow the surface parameterization.
+    pub fn build_from_normal_tangent(n: &Vec3, tangent: &Vec3) -> Self {
+        let w = Vec3::unit_vector(n);
+        let t = tangent - w * w.dot(tangent);
+        if t.near_zero() {
+            return Self::build_from_w(&w);
+        }
+        let u = Vec3::unit_vector(&t);
+        let v = w.cross(&u);
+
+        Self { u, v, w }
+    }
+
+    pub fn local(&self, a: f64, b: f64, c: f64) -> Vec3 {
+        self.u * a + self.v * b + self.w * c
+    }
+
+    pub fn local_vec(&self, a: &Vec3) -> Vec3 {
+        self.local(a.x(), a.y(), a.z())
+    }
+
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
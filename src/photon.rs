@@ -0,0 +1,350 @@
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::light::LightSampling;
+use crate::material::{LightReflection, MaterialType};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::utils::random_number;
+use crate::vec::Vec3;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f64::consts::PI;
+
+// Photons emitted from the lights when building a map.
+pub const PHOTON_COUNT: usize = 1_000_000;
+
+// Photons gathered by a radiance estimate, and the largest radius searched
+// for them in scene units.
+const ESTIMATE_PHOTONS: usize = 64;
+const MAX_RADIUS: f64 = 0.2;
+
+// Photons landing on a surface facing too far from the shading point are
+// left out of its estimate.
+const MIN_NORMAL_COSINE: f64 = 0.5;
+
+// Light flux arriving at a diffuse surface.
+#[derive(Clone, Copy)]
+pub struct Photon {
+    pub p: Vec3,
+    // Direction the photon came from, pointing away from the surface.
+    pub wi: Vec3,
+    pub normal: Vec3,
+    pub power: Color,
+    // Index of the light that emitted the photon.
+    pub light: usize,
+}
+
+// Caustic photon map (Jensen 1996): photons that reached a diffuse surface
+// after one or more specular bounces, stored in a balanced kd-tree. The path
+// tracer handles every other kind of path. Lights at infinity don't emit
+// photons, and spectral renders see the RGB estimate at their wavelength.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    // Split axis of the subtree rooted at each photon.
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    pub fn build(scene: &Scene, count: usize, max_depth: i32) -> Self {
+        let mut photons = Vec::new();
+        if !scene.lights().is_empty() {
+            for _ in 0..count {
+                trace_photon(scene, count, max_depth, &mut photons);
+            }
+        }
+
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    // Radiance reflected towards the ray from the photons around the hit,
+    // spread over the disc holding the nearest ones. Calls `visit` with the
+    // radiance carried by each photon and the light that emitted it.
+    pub fn estimate(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        m: &MaterialType,
+        mut visit: impl FnMut(usize, Color),
+    ) {
+        let mut nearest = BinaryHeap::new();
+        self.nearest(
+            0,
+            self.photons.len(),
+            &rec.p,
+            MAX_RADIUS * MAX_RADIUS,
+            &mut nearest,
+        );
+        if nearest.is_empty() {
+            return;
+        }
+
+        let radius_squared = if nearest.len() == ESTIMATE_PHOTONS {
+            nearest
+                .peek()
+                .map_or(MAX_RADIUS * MAX_RADIUS, |n| n.dist_squared)
+        } else {
+            MAX_RADIUS * MAX_RADIUS
+        };
+
+        let normal = rec.outward_normal();
+        let area = PI * radius_squared;
+        for n in nearest {
+            let photon = &self.photons[n.index];
+            if photon.normal.dot(&normal) < MIN_NORMAL_COSINE {
+                continue;
+            }
+            // eval includes the cosine at the photon direction, which the
+            // flux already accounts for.
+            let cosine = f64::abs(rec.normal.dot(&photon.wi));
+            if cosine < 1e-4 {
+                continue;
+            }
+            let flux = m.eval(r_in, rec, &photon.wi) * photon.power / cosine;
+            visit(photon.light, flux / area);
+        }
+    }
+
+    // Collects the photons closest to `p` in the subtree covering
+    // `photons[lo..hi]`, keeping at most `ESTIMATE_PHOTONS` of them.
+    fn nearest(
+        &self,
+        lo: usize,
+        hi: usize,
+        p: &Vec3,
+        max_dist_squared: f64,
+        nearest: &mut BinaryHeap<Neighbour>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+        let axis = self.axes[mid];
+        let d = component(p, axis) - component(&photon.p, axis);
+        let (near, far) = if d < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+
+        self.nearest(near.0, near.1, p, max_dist_squared, nearest);
+
+        let dist_squared = (photon.p - p).norm_squared();
+        if dist_squared < search_radius(nearest, max_dist_squared) {
+            nearest.push(Neighbour {
+                dist_squared,
+                index: mid,
+            });
+            if nearest.len() > ESTIMATE_PHOTONS {
+                nearest.pop();
+            }
+        }
+
+        if d * d < search_radius(nearest, max_dist_squared) {
+            self.nearest(far.0, far.1, p, max_dist_squared, nearest);
+        }
+    }
+}
+
+// Squared radius still worth searching, shrinking once enough photons are found.
+fn search_radius(nearest: &BinaryHeap<Neighbour>, max_dist_squared: f64) -> f64 {
+    if nearest.len() < ESTIMATE_PHOTONS {
+        return max_dist_squared;
+    }
+    nearest.peek().map_or(max_dist_squared, |n| n.dist_squared)
+}
+
+// Photon found by a search, ordered by distance so the heap top is the farthest.
+struct Neighbour {
+    dist_squared: f64,
+    index: usize,
+}
+
+impl PartialEq for Neighbour {
+    fn eq(&self, other: &Self) -> bool {
+        self.dist_squared == other.dist_squared
+    }
+}
+
+impl Eq for Neighbour {}
+
+impl PartialOrd for Neighbour {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbour {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist_squared.total_cmp(&other.dist_squared)
+    }
+}
+
+fn component(v: &Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x(),
+        1 => v.y(),
+        _ => v.z(),
+    }
+}
+
+// Emits one photon from a light picked uniformly and follows it through
+// specular bounces, storing it where it lands on a diffuse surface.
+fn trace_photon(scene: &Scene, count: usize, max_depth: i32, photons: &mut Vec<Photon>) {
+    let light_count = scene.lights().len();
+    let index = usize::min(
+        (random_number() * light_count as f64) as usize,
+        light_count - 1,
+    );
+    let es = match scene.lights()[index].sample_le() {
+        Some(es) if es.pdf_pos > 0.0 && es.pdf_dir > 0.0 && !es.le.is_black() => es,
+        _ => return,
+    };
+
+    let cosine = if es.normal.near_zero() {
+        1.0
+    } else {
+        f64::abs(es.normal.dot(&es.direction))
+    };
+    let mut power =
+        es.le * (cosine * light_count as f64 / (es.pdf_pos * es.pdf_dir * count as f64));
+    let mut origin = es.origin;
+    let mut direction = es.direction;
+
+    // Every bounce before the photon lands is specular.
+    for bounce in 0..max_depth {
+        let r = Ray::new(&origin, &direction);
+        let mut rec = HitRecord::new();
+        let (is_hit, hit_material) = scene.world.hit(&r, 0.001, f64::INFINITY, &mut rec);
+        if !is_hit {
+            return;
+        }
+        let m = MaterialType::resolve(&hit_material, &r, &rec);
+
+        // Media are left to the random walks of the path tracer.
+        if m.medium().is_some() {
+            return;
+        }
+
+        if !m.is_specular() {
+            // Light reaching diffuse surfaces directly is left to the path tracer.
+            if bounce > 0 {
+                photons.push(Photon {
+                    p: rec.p,
+                    wi: -Vec3::unit_vector(&direction),
+                    normal: rec.outward_normal(),
+                    power,
+                    light: index,
+                });
+            }
+            return;
+        }
+
+        let mut attenuation = Color::new(0.0, 0.0, 0.0);
+        let (is_scattered, scattered) = m.scatter(&r, &rec, &mut attenuation);
+        if !is_scattered {
+            return;
+        }
+        power *= attenuation;
+
+        // Russian roulette keeps the photon powers even.
+        let survival = f64::min(attenuation.max_component(), 1.0);
+        if random_number() >= survival {
+            return;
+        }
+        power *= 1.0 / survival;
+
+        origin = rec.p;
+        direction = scattered;
+    }
+}
+
+// Arranges `photons` as an implicit balanced kd-tree: the median of each
+// range along its widest axis sits in the middle, smaller photons before it.
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.len() <= 1 {
+        return;
+    }
+
+    let (mut min, mut max) = (photons[0].p, photons[0].p);
+    for photon in photons.iter() {
+        min = Vec3::new(
+            min.x().min(photon.p.x()),
+            min.y().min(photon.p.y()),
+            min.z().min(photon.p.z()),
+        );
+        max = Vec3::new(
+            max.x().max(photon.p.x()),
+            max.y().max(photon.p.y()),
+            max.z().max(photon.p.z()),
+        );
+    }
+    let extent = max - min;
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+        0
+    } else if extent.y() > extent.z() {
+        1
+    } else {
+        2
+    };
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| {
+        component(&a.p, axis).total_cmp(&component(&b.p, axis))
+    });
+    axes[mid] = axis;
+
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_point() -> Vec3 {
+        Vec3::new(random_number(), random_number(), random_number())
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let mut photons: Vec<Photon> = (0..2000)
+            .map(|_| Photon {
+                p: random_point(),
+                wi: Vec3::new(0.0, 1.0, 0.0),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+                power: Color::new(1.0, 1.0, 1.0),
+                light: 0,
+            })
+            .collect();
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        let map = PhotonMap { photons, axes };
+
+        // Radii both below and above the one holding ESTIMATE_PHOTONS photons.
+        for max_dist_squared in [0.001, 0.01, 0.04, 1.0] {
+            for _ in 0..50 {
+                let p = random_point() * 1.2 - Vec3::new(0.1, 0.1, 0.1);
+                let mut nearest = BinaryHeap::new();
+                map.nearest(0, map.photons.len(), &p, max_dist_squared, &mut nearest);
+                let mut found: Vec<f64> = nearest.into_iter().map(|n| n.dist_squared).collect();
+                found.sort_by(f64::total_cmp);
+
+                let mut expected: Vec<f64> = map
+                    .photons
+                    .iter()
+                    .map(|photon| (photon.p - p).norm_squared())
+                    .filter(|&d| d < max_dist_squared)
+                    .collect();
+                expected.sort_by(f64::total_cmp);
+                expected.truncate(ESTIMATE_PHOTONS);
+
+                assert_eq!(found, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use crate::color::Color;
+use crate::fresnel::{fresnel_schlick, schlick_weight};
+use crate::hittable::HitRecord;
+use crate::material::{shading_frame, LightReflection, RoughInterface};
+use crate::microfacet::TrowbridgeReitz;
+use crate::node::Node;
+use crate::ray::Ray;
+use crate::utils::random_number;
+use crate::vec::Vec3;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// Keeps every lobe rough enough to be evaluated for light sampling.
+const MIN_ALPHA: f64 = 0.002;
+
+// Parameters of the principled BSDF, each driven by a shading node. Scalar
+// parameters read the luminance of the node.
+#[derive(Clone, Copy)]
+pub enum PrincipledInput {
+    BaseColor,
+    Metallic,
+    Roughness,
+    Specular,
+    SpecularTint,
+    Sheen,
+    SheenTint,
+    Clearcoat,
+    ClearcoatGloss,
+    Transmission,
+    Ior,
+}
+
+// Disney principled BSDF (Burley 2012, 2015): a diffuse base with sheen, a
+// tinted GGX specular lobe, a GTR1 clearcoat and a rough glass lobe for
+// transmission, blended by the metallic and transmission parameters.
+pub struct Principled {
+    pub base_color: Rc<Node>,
+    pub metallic: Rc<Node>,
+    pub roughness: Rc<Node>,
+    pub specular: Rc<Node>,
+    pub specular_tint: Rc<Node>,
+    pub sheen: Rc<Node>,
+    pub sheen_tint: Rc<Node>,
+    pub clearcoat: Rc<Node>,
+    pub clearcoat_gloss: Rc<Node>,
+    pub transmission: Rc<Node>,
+    pub ior: Rc<Node>,
+}
+
+impl Principled {
+    pub fn new(base_color: Color) -> Self {
+        Self {
+            base_color: Node::constant(base_color),
+            metallic: Node::uniform(0.0),
+            roughness: Node::uniform(0.5),
+            specular: Node::uniform(0.5),
+            specular_tint: Node::uniform(0.0),
+            sheen: Node::uniform(0.0),
+            sheen_tint: Node::uniform(0.5),
+            clearcoat: Node::uniform(0.0),
+            clearcoat_gloss: Node::uniform(1.0),
+            transmission: Node::uniform(0.0),
+            ior: Node::uniform(1.5),
+        }
+    }
+
+    pub fn with_input(mut self, input: PrincipledInput, node: Rc<Node>) -> Self {
+        let slot = match input {
+            PrincipledInput::BaseColor => &mut self.base_color,
+            PrincipledInput::Metallic => &mut self.metallic,
+            PrincipledInput::Roughness => &mut self.roughness,
+            PrincipledInput::Specular => &mut self.specular,
+            PrincipledInput::SpecularTint => &mut self.specular_tint,
+            PrincipledInput::Sheen => &mut self.sheen,
+            PrincipledInput::SheenTint => &mut self.sheen_tint,
+            PrincipledInput::Clearcoat => &mut self.clearcoat,
+            PrincipledInput::ClearcoatGloss => &mut self.clearcoat_gloss,
+            PrincipledInput::Transmission => &mut self.transmission,
+            PrincipledInput::Ior => &mut self.ior,
+        };
+        *slot = node;
+        self
+    }
+
+    // The BSDF with every parameter evaluated at the hit.
+    fn at(&self, r_in: &Ray, rec: &HitRecord) -> PrincipledBsdf {
+        let scalar = |node: &Rc<Node>| node.eval_scalar(r_in, rec).clamp(0.0, 1.0);
+        PrincipledBsdf {
+            base_color: self.base_color.eval(r_in, rec),
+            metallic: scalar(&self.metallic),
+            roughness: scalar(&self.roughness),
+            specular: scalar(&self.specular),
+            specular_tint: scalar(&self.specular_tint),
+            sheen: scalar(&self.sheen),
+            sheen_tint: scalar(&self.sheen_tint),
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_gloss: scalar(&self.clearcoat_gloss),
+            transmission: scalar(&self.transmission),
+            ior: self.ior.eval_scalar(r_in, rec).max(1.0),
+        }
+    }
+}
+
+impl LightReflection for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        self.at(r_in, rec).scatter(r_in, rec, attenuation)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        self.at(r_in, rec).eval(r_in, rec, scattered)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        self.at(r_in, rec).pdf(r_in, rec, scattered)
+    }
+}
+
+struct PrincipledBsdf {
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    specular_tint: f64,
+    sheen: f64,
+    sheen_tint: f64,
+    clearcoat: f64,
+    clearcoat_gloss: f64,
+    transmission: f64,
+    ior: f64,
+}
+
+impl PrincipledBsdf {
+    fn distribution(&self) -> TrowbridgeReitz {
+        let alpha = f64::max(
+            TrowbridgeReitz::roughness_to_alpha(self.roughness),
+            MIN_ALPHA,
+        );
+        TrowbridgeReitz::new(alpha, alpha)
+    }
+
+    fn glass(&self) -> RoughInterface {
+        RoughInterface {
+            ir: self.ior,
+            distribution: self.distribution(),
+            absorption: Color::new(0.0, 0.0, 0.0),
+            dispersion: None,
+        }
+    }
+
+    // Base color normalized to unit luminance, used for the tint parameters.
+    fn tint(&self) -> Color {
+        let luminance = self.base_color.luminance();
+        if luminance <= 0.0 {
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        self.base_color * (1.0 / luminance)
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        0.1 * (1.0 - self.clearcoat_gloss) + 0.001 * self.clearcoat_gloss
+    }
+
+    // Sampling probabilities of the diffuse, specular, clearcoat and glass lobes.
+    fn lobe_weights(&self, rec: &HitRecord) -> [f64; 4] {
+        if self.is_inside(rec) {
+            return [0.0, 0.0, 0.0, 1.0];
+        }
+        let diffuse = (1.0 - self.metallic) * (1.0 - self.transmission);
+        let glass = (1.0 - self.metallic) * self.transmission;
+        let specular = 1.0 - glass;
+        let clearcoat = 0.25 * self.clearcoat;
+
+        let total = diffuse + specular + clearcoat + glass;
+        [
+            diffuse / total,
+            specular / total,
+            clearcoat / total,
+            glass / total,
+        ]
+    }
+
+    // Reflection lobes, BSDF times cosine, for local directions above the surface.
+    fn eval_reflection(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return black;
+        }
+
+        let wm = Vec3::unit_vector(&(*wi + wo));
+        let cos_d = wi.dot(&wm);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let tint = self.tint();
+        let dielectric = (1.0 - self.metallic) * (1.0 - self.transmission);
+
+        // Burley diffuse with retro-reflection at grazing angles.
+        let fl = schlick_weight(wi.z());
+        let fv = schlick_weight(wo.z());
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+        let mut f = self.base_color * (fd / PI * dielectric);
+
+        let sheen_color = white * (1.0 - self.sheen_tint) + tint * self.sheen_tint;
+        f += sheen_color * (self.sheen * schlick_weight(cos_d) * dielectric);
+
+        // Specular, tinted towards the base color for metals.
+        let specular_color = (white * (1.0 - self.specular_tint) + tint * self.specular_tint)
+            * (0.08 * self.specular);
+        let f0 = specular_color * (1.0 - self.metallic) + self.base_color * self.metallic;
+        let distribution = self.distribution();
+        let specular = distribution.d(&wm) * distribution.g(wo, wi) / (4.0 * wo.z() * wi.z());
+        f += fresnel_schlick(&f0, cos_d) * (specular * (1.0 - self.glass_weight()));
+
+        if self.clearcoat > 0.0 {
+            let coat = TrowbridgeReitz::new(0.25, 0.25);
+            let dr = gtr1(wm.z(), self.clearcoat_alpha());
+            let fr = 0.04 + 0.96 * schlick_weight(cos_d);
+            let gr = coat.g1(wo) * coat.g1(wi);
+            f += white * (0.25 * self.clearcoat * dr * fr * gr / (4.0 * wo.z() * wi.z()));
+        }
+
+        f * wi.z()
+    }
+
+    fn glass_weight(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    // Only the glass lobe exists inside a transmissive object, opaque ones are
+    // treated as two sided.
+    fn is_inside(&self, rec: &HitRecord) -> bool {
+        !rec.front_face && self.glass_weight() > 0.0
+    }
+
+    // Glass lobe, with light tinted by the square root of the base color each
+    // time it crosses the surface.
+    fn eval_glass(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3, wi: &Vec3) -> Color {
+        let f = self.glass().eval(r_in, rec, scattered);
+        if wi.z() < 0.0 {
+            return f * Color::new(
+                self.base_color.r().sqrt(),
+                self.base_color.g().sqrt(),
+                self.base_color.b().sqrt(),
+            );
+        }
+        f
+    }
+}
+
+impl LightReflection for PrincipledBsdf {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let weights = self.lobe_weights(rec);
+
+        let u = random_number();
+        let scattered = if u < weights[0] {
+            // Cosine weighted hemisphere for the diffuse and sheen lobes.
+            rec.normal + Vec3::random_unit_vector()
+        } else if u < weights[0] + weights[1] {
+            let wm = self
+                .distribution()
+                .sample_wm(&wo, random_number(), random_number());
+            uvw.local_vec(&Vec3::reflect(&-wo, &wm))
+        } else if u < weights[0] + weights[1] + weights[2] {
+            let wm = sample_gtr1(self.clearcoat_alpha());
+            uvw.local_vec(&Vec3::reflect(&-wo, &wm))
+        } else {
+            let mut glass_attenuation = Color::new(0.0, 0.0, 0.0);
+            let (is_scattered, direction) = self.glass().scatter(r_in, rec, &mut glass_attenuation);
+            if !is_scattered {
+                return (false, rec.normal);
+            }
+            direction
+        };
+
+        if scattered.near_zero() {
+            return (false, rec.normal);
+        }
+
+        let pdf = self.pdf(r_in, rec, &scattered);
+        if pdf <= 0.0 {
+            return (false, rec.normal);
+        }
+        *attenuation = self.eval(r_in, rec, &scattered) * (1.0 / pdf);
+        (true, scattered)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> Color {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+
+        if self.is_inside(rec) {
+            return self.eval_glass(r_in, rec, scattered, &wi);
+        }
+
+        let mut f = self.eval_reflection(&wo, &wi);
+        if self.glass_weight() > 0.0 {
+            f += self.eval_glass(r_in, rec, scattered, &wi) * self.glass_weight();
+        }
+        f
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Vec3) -> f64 {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        let wi = uvw.to_local(&Vec3::unit_vector(scattered));
+        let weights = self.lobe_weights(rec);
+
+        let mut pdf = 0.0;
+        if weights[3] > 0.0 {
+            pdf += weights[3] * self.glass().pdf(r_in, rec, scattered);
+        }
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return pdf;
+        }
+
+        let wm = Vec3::unit_vector(&(wi + wo));
+        pdf += weights[0] * wi.z() / PI;
+        pdf += weights[1] * self.distribution().d_visible(&wo, &wm) / (4.0 * wo.dot(&wm));
+        pdf += weights[2] * gtr1(wm.z(), self.clearcoat_alpha()) * wm.z() / (4.0 * wo.dot(&wm));
+        pdf
+    }
+}
+
+// Generalized Trowbridge-Reitz distribution with gamma = 1, used by the clearcoat.
+fn gtr1(cos_theta_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_theta_h * cos_theta_h))
+}
+
+fn sample_gtr1(alpha: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_theta = f64::sqrt((1.0 - a2.powf(1.0 - random_number())) / (1.0 - a2));
+    let sin_theta = f64::sqrt(f64::max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * PI * random_number();
+    Vec3::new(
+        sin_theta * f64::cos(phi),
+        sin_theta * f64::sin(phi),
+        cos_theta,
+    )
+}
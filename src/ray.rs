@@ -4,11 +4,25 @@ use crate::vec::Vec3;
 pub struct Ray<'a> {
     pub origin: &'a Vec3,
     pub dir: &'a Vec3,
+    // Wavelength in nanometers carried by the ray in spectral mode.
+    pub wavelength: Option<f64>,
 }
 
 impl<'a> Ray<'a> {
     pub fn new(origin: &'a Vec3, dir: &'a Vec3) -> Self {
-        Self { origin, dir }
+        Self {
+            origin,
+            dir,
+            wavelength: None,
+        }
+    }
+
+    pub fn with_wavelength(origin: &'a Vec3, dir: &'a Vec3, wavelength: Option<f64>) -> Self {
+        Self {
+            origin,
+            dir,
+            wavelength,
+        }
     }
 
     pub fn origin(&self) -> &Vec3 {
@@ -0,0 +1,215 @@
+use crate::utils::random_number;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// Largest f64 below one, keeping wrapped samples inside [0, 1).
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+// Source of the uniform numbers in [0, 1) an integrator draws.
+pub trait Sampler {
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Independent uniform numbers from `random_number`.
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn get_1d(&mut self) -> f64 {
+        random_number()
+    }
+}
+
+// One coordinate of the primary sample vector, with the state to undo its
+// last mutation.
+#[derive(Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    last_modified: u64,
+    value_backup: f64,
+    modified_backup: u64,
+}
+
+// Primary sample space sampler (Kelemen et al. 2002). A path is a function of
+// the uniform numbers it consumes, so mutating those numbers mutates the
+// path. Each iteration either redraws every sample (a large step) or
+// perturbs them slightly. Coordinates are mutated lazily, when a path first
+// asks for them, catching up on the small steps they missed.
+pub struct MltSampler {
+    rng: StdRng,
+    sigma: f64,
+    large_step_probability: f64,
+    samples: Vec<PrimarySample>,
+    iteration: u64,
+    last_large_step: u64,
+    large_step: bool,
+    index: usize,
+}
+
+impl MltSampler {
+    // Samplers with the same seed produce the same first path.
+    pub fn new(seed: u64, sigma: f64, large_step_probability: f64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+            large_step_probability,
+            samples: Vec::new(),
+            iteration: 0,
+            last_large_step: 0,
+            large_step: true,
+            index: 0,
+        }
+    }
+
+    // Proposes a new path, to be kept with `accept` or undone with `reject`.
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    pub fn reject(&mut self) {
+        for x in self.samples.iter_mut() {
+            if x.last_modified == self.iteration {
+                x.value = x.value_backup;
+                x.last_modified = x.modified_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    // Uniform number outside the primary sample vector, for decisions about
+    // the chain itself.
+    pub fn uniform(&mut self) -> f64 {
+        self.rng.gen()
+    }
+
+    fn next(&mut self) -> f64 {
+        // Coordinates a path asks for the first time start out uniform, as if
+        // drawn at the latest large step.
+        if self.index == self.samples.len() {
+            self.samples.push(PrimarySample {
+                value: self.rng.gen(),
+                last_modified: self.last_large_step,
+                ..PrimarySample::default()
+            });
+        }
+        let iteration = self.iteration;
+        let x = &mut self.samples[self.index];
+        self.index += 1;
+
+        // Samples last touched before the latest large step are stale.
+        if x.last_modified < self.last_large_step {
+            x.value = self.rng.gen();
+            x.last_modified = self.last_large_step;
+        }
+
+        x.value_backup = x.value;
+        x.modified_backup = x.last_modified;
+        if self.large_step {
+            x.value = self.rng.gen();
+        } else {
+            // The sum of n normal steps is one step with sqrt(n) times the
+            // deviation. Box-Muller transform for the normal sample.
+            let steps = (iteration - x.last_modified) as f64;
+            let u1: f64 = 1.0 - self.rng.gen::<f64>();
+            let u2: f64 = self.rng.gen();
+            let normal = f64::sqrt(-2.0 * u1.ln()) * f64::cos(2.0 * PI * u2);
+            x.value += normal * self.sigma * steps.sqrt();
+            x.value = f64::min(x.value - x.value.floor(), ONE_MINUS_EPSILON);
+        }
+        x.last_modified = iteration;
+        x.value
+    }
+}
+
+// Metropolis sampler shared by the integrator, which is handed it explicitly,
+// and by the materials and lights, which reach it through `random_number`
+// while it drives the thread.
+pub type SharedMltSampler = Rc<RefCell<MltSampler>>;
+
+impl Sampler for MltSampler {
+    fn get_1d(&mut self) -> f64 {
+        self.next()
+    }
+}
+
+impl Sampler for SharedMltSampler {
+    fn get_1d(&mut self) -> f64 {
+        self.borrow_mut().next()
+    }
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<SharedMltSampler>> = const { RefCell::new(None) };
+}
+
+// Runs `f` with every `random_number` call drawing from `sampler`.
+pub fn drive<R>(sampler: &SharedMltSampler, f: impl FnOnce() -> R) -> R {
+    ACTIVE.with(|active| *active.borrow_mut() = Some(Rc::clone(sampler)));
+    let result = f();
+    ACTIVE.with(|active| active.borrow_mut().take());
+    result
+}
+
+// Next primary sample when a sampler is driving the current thread.
+pub fn next_sample() -> Option<f64> {
+    ACTIVE.with(|active| active.borrow().as_ref().map(|s| s.borrow_mut().next()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(sampler: &MltSampler) -> Vec<(f64, u64)> {
+        sampler
+            .samples
+            .iter()
+            .map(|x| (x.value, x.last_modified))
+            .collect()
+    }
+
+    #[test]
+    fn reject_restores_the_previous_state() {
+        let mut sampler = MltSampler::new(7, 0.01, 0.3);
+        for _ in 0..20 {
+            sampler.start_iteration();
+            for _ in 0..4 {
+                sampler.get_1d();
+            }
+            sampler.accept();
+        }
+
+        let iteration = sampler.iteration;
+        let last_large_step = sampler.last_large_step;
+        let before = values(&sampler);
+        for _ in 0..20 {
+            sampler.start_iteration();
+            for _ in 0..4 {
+                sampler.get_1d();
+            }
+            sampler.reject();
+
+            assert_eq!(sampler.iteration, iteration);
+            assert_eq!(sampler.last_large_step, last_large_step);
+            assert_eq!(values(&sampler), before);
+        }
+
+        // The next proposal still mutates the accepted samples.
+        sampler.start_iteration();
+        let proposed: Vec<f64> = (0..4).map(|_| sampler.get_1d()).collect();
+        assert_ne!(proposed, before.iter().map(|x| x.0).collect::<Vec<_>>());
+    }
+}
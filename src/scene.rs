@@ -0,0 +1,79 @@
+use crate::environment::{Environment, Gradient};
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::light::{LightSampling, LightType};
+use crate::material::MaterialType;
+use crate::vec::Vec3;
+use std::rc::Rc;
+
+pub struct Scene {
+    pub world: HittableList<dyn Hittable>,
+    pub environment: Rc<dyn Environment>,
+    pub group_names: Vec<String>,
+    // Added through `add_light`, which keeps the light group of each light,
+    // indexing `group_names`, for rendering the contribution of each group to
+    // its own pass.
+    lights: Vec<LightType>,
+    light_groups: Vec<usize>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            world: HittableList::new(),
+            environment: Rc::new(Gradient::default()),
+            group_names: vec!["default".to_string()],
+            lights: Vec::new(),
+            light_groups: Vec::new(),
+        }
+    }
+
+    pub fn add_light(&mut self, light: LightType, group: &str) {
+        let index = match self.group_names.iter().position(|name| name == group) {
+            Some(index) => index,
+            None => {
+                self.group_names.push(group.to_string());
+                self.group_names.len() - 1
+            }
+        };
+        self.light_groups.push(index);
+        self.lights.push(light);
+    }
+
+    pub fn lights(&self) -> &[LightType] {
+        &self.lights
+    }
+
+    pub fn light_group(&self, light: usize) -> usize {
+        self.light_groups[light]
+    }
+
+    // Pdf of light sampling choosing the emitter with material `m` in direction `wi`,
+    // including the probability of picking that light among all of them.
+    pub fn light_pdf(&self, m: &Rc<MaterialType>, p: &Vec3, wi: &Vec3) -> f64 {
+        let light_count = self.lights.len() as f64;
+
+        self.lights
+            .iter()
+            .filter(|l| l.owns(m))
+            .map(|l| l.pdf_li(p, wi) / light_count)
+            .sum()
+    }
+
+    // Pdf of light sampling choosing the environment in direction `wi`.
+    pub fn environment_pdf(&self, wi: &Vec3) -> f64 {
+        let light_count = self.lights.len() as f64;
+
+        self.lights
+            .iter()
+            .filter(|l| matches!(l, LightType::Environment(_)))
+            .map(|l| l.pdf_li(&Vec3::new(0.0, 0.0, 0.0), wi) / light_count)
+            .sum()
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,919 @@
+// Plain text scene description, one statement per line:
+//
+//   # comment
+//   material <name> <type> key=value ...
+//   texture <name> image path=<file>
+//   node <name> <type> key=value ...
+//   sphere center=x,y,z radius=r material=<name> [group=<name>]
+//   triangle v0=x,y,z v1=x,y,z v2=x,y,z [uv0=u,v uv1=u,v uv2=u,v] material=<name>
+//            [group=<name>]
+//   mesh path=<file.obj> [scale=s] [translate=x,y,z] [material=<name>] [group=<name>]
+//   light <type> key=value ... [group=<name>]
+//   environment <type> key=value ... [group=<name>]
+//
+// Spheres, triangles and mesh triangles with an emissive material are
+// registered as area lights. Lights, environments and emissive shapes go in the
+// light group given by `group`, or "default". Material colors and scalars, and the node inputs
+// themselves, accept either a literal value or a declared node written
+// `@name`. Parameters of the medium inside dielectrics and subsurface
+// materials, and the index of layered coats, only take literal values.
+use crate::bump::Bumped;
+use crate::color::Color;
+use crate::environment::{Environment, EnvironmentMap, Gradient};
+use crate::fresnel::ThinFilm;
+use crate::layered::Layered;
+use crate::light::{
+    AreaLight, DirectionalLight, EnvironmentLight, LightType, PointLight, SpotLight, TriangleLight,
+};
+use crate::material::{
+    Conductor, Dielectric, DiffuseLight, Lambertian, Masked, MaterialType, Metal, Mix, OrenNayar,
+    RoughDielectric, Translucent,
+};
+use crate::measured::Measured;
+use crate::node::{Node, Perlin};
+use crate::obj;
+use crate::principled::{Principled, PrincipledInput};
+use crate::scene::Scene;
+use crate::sky::{sun_direction, PreethamSky};
+use crate::spectrum::Dispersion;
+use crate::sphere::Sphere;
+use crate::subsurface::Subsurface;
+use crate::texture::ImageTexture;
+use crate::triangle::Triangle;
+use crate::vec::Vec3;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::rc::Rc;
+
+pub fn load(path: &str) -> std::io::Result<Scene> {
+    parse(&fs::read_to_string(path)?)
+}
+
+pub fn parse(source: &str) -> std::io::Result<Scene> {
+    let mut scene = Scene::new();
+    let mut materials: HashMap<String, Rc<MaterialType>> = HashMap::new();
+    let mut textures: HashMap<String, Rc<ImageTexture>> = HashMap::new();
+    let mut nodes: HashMap<String, Rc<Node>> = HashMap::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let line_number = index + 1;
+        match words[0] {
+            "material" => {
+                if words.len() < 3 {
+                    return Err(invalid(line_number, "expected material <name> <type>"));
+                }
+                let params = Params::new(&words[3..], line_number)?;
+                let material = parse_material(words[2], &params, &materials, &textures, &nodes)?;
+                materials.insert(words[1].to_string(), Rc::new(material));
+            }
+            "texture" => {
+                if words.len() < 3 || words[2] != "image" {
+                    return Err(invalid(line_number, "expected texture <name> image"));
+                }
+                let params = Params::new(&words[3..], line_number)?;
+                let texture = ImageTexture::load(params.string("path")?)?;
+                textures.insert(words[1].to_string(), Rc::new(texture));
+            }
+            "node" => {
+                if words.len() < 3 {
+                    return Err(invalid(line_number, "expected node <name> <type>"));
+                }
+                let params = Params::new(&words[3..], line_number)?;
+                let node = parse_node(words[2], &params, &textures, &nodes)?;
+                nodes.insert(words[1].to_string(), Rc::new(node));
+            }
+            "sphere" => {
+                let params = Params::new(&words[1..], line_number)?;
+                let m = lookup(&materials, &params, "material")?;
+
+                let sphere = Rc::new(Sphere::new(
+                    params.vec3("center")?,
+                    params.f64("radius")?,
+                    Rc::clone(&m),
+                ));
+                if m.is_emissive() {
+                    scene.add_light(
+                        LightType::Area(AreaLight::new(sphere.clone())),
+                        params.string_or("group", "default"),
+                    );
+                }
+                scene.world.add(sphere);
+            }
+            "triangle" => {
+                let params = Params::new(&words[1..], line_number)?;
+                let m = lookup(&materials, &params, "material")?;
+                let (v0, v1, v2) = (params.vec3("v0")?, params.vec3("v1")?, params.vec3("v2")?);
+                if m.is_emissive() {
+                    let shape = Triangle::new(v0, v1, v2, Rc::clone(&m));
+                    scene.add_light(
+                        LightType::Triangle(TriangleLight::new(shape)),
+                        params.string_or("group", "default"),
+                    );
+                }
+                let mut triangle = Triangle::new(v0, v1, v2, m);
+                if params.values.contains_key("uv0") {
+                    let uv = |key| -> std::io::Result<(f64, f64)> {
+                        let n = params.numbers(key, 2)?;
+                        Ok((n[0], n[1]))
+                    };
+                    triangle = triangle.with_uvs([uv("uv0")?, uv("uv1")?, uv("uv2")?]);
+                }
+                scene.world.add(Rc::new(triangle));
+            }
+            "mesh" => {
+                let params = Params::new(&words[1..], line_number)?;
+                let scale = params.f64_or("scale", 1.0)?;
+                let translate = if params.values.contains_key("translate") {
+                    params.vec3("translate")?
+                } else {
+                    Vec3::new(0.0, 0.0, 0.0)
+                };
+                // A material given here replaces the ones from the .mtl files.
+                let (fallback, override_materials) = if params.values.contains_key("material") {
+                    (lookup(&materials, &params, "material")?, true)
+                } else {
+                    let grey = Lambertian::new(Color::new(0.8, 0.8, 0.8));
+                    (Rc::new(MaterialType::Lambertian(grey)), false)
+                };
+                let mesh = obj::load(
+                    params.string("path")?,
+                    |p| p * scale + translate,
+                    fallback,
+                    override_materials,
+                )?;
+                let group = params.string_or("group", "default");
+                for t in &mesh.triangles {
+                    if t.m.is_emissive() {
+                        let shape = Triangle::new(t.v0, t.v1, t.v2, Rc::clone(&t.m));
+                        scene.add_light(LightType::Triangle(TriangleLight::new(shape)), group);
+                    }
+                }
+                scene.world.add(Rc::new(mesh));
+            }
+            "light" if words.len() >= 2 => {
+                let params = Params::new(&words[2..], line_number)?;
+                let light = parse_light(words[1], &params)?;
+                scene.add_light(light, params.string_or("group", "default"));
+            }
+            "environment" if words.len() >= 2 => {
+                let params = Params::new(&words[2..], line_number)?;
+                parse_environment(words[1], &params, &mut scene)?;
+            }
+            _ => return Err(invalid(line_number, &format!("unknown statement {}", line))),
+        }
+    }
+
+    Ok(scene)
+}
+
+fn parse_material(
+    kind: &str,
+    params: &Params,
+    materials: &HashMap<String, Rc<MaterialType>>,
+    textures: &HashMap<String, Rc<ImageTexture>>,
+    nodes: &HashMap<String, Rc<Node>>,
+) -> std::io::Result<MaterialType> {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let material = match kind {
+        "lambertian" => MaterialType::Lambertian(
+            Lambertian::new(black).with_albedo(node_input(nodes, params, "albedo")?),
+        ),
+        "oren_nayar" => MaterialType::OrenNayar(
+            OrenNayar::new(black, 0.0)
+                .with_albedo(node_input(nodes, params, "albedo")?)
+                .with_sigma(node_input_or(nodes, params, "sigma", 20.0)?),
+        ),
+        "translucent" => MaterialType::Translucent(
+            Translucent::new(black, black)
+                .with_reflectance(node_input(nodes, params, "reflectance")?)
+                .with_transmittance(node_input(nodes, params, "transmittance")?),
+        ),
+        "metal" => MaterialType::Metal(
+            Metal::new(black, 0.0)
+                .with_albedo(node_input(nodes, params, "albedo")?)
+                .with_fuzz(node_input_or(nodes, params, "fuzz", 0.0)?),
+        ),
+        "dielectric" => {
+            let dispersion = dispersion(params)?;
+            let mut dielectric = Dielectric::new(index_of_refraction(params, &dispersion)?)
+                .with_absorption(absorption(params)?);
+            if let Some(dispersion) = dispersion {
+                dielectric = dielectric.with_dispersion(dispersion);
+            }
+            if let Some(film) = thin_film(params)? {
+                dielectric = dielectric.with_thin_film(film);
+            }
+            MaterialType::Dielectric(dielectric)
+        }
+        "light" => MaterialType::DiffuseLight(DiffuseLight::new(params.color("emit")?)),
+        "conductor" => {
+            let mut conductor = match params.string_or("preset", "") {
+                "gold" => Conductor::gold(0.0),
+                "copper" => Conductor::copper(0.0),
+                "aluminium" => Conductor::aluminium(0.0),
+                "" => Conductor::new(black, black, 0.0)
+                    .with_eta(node_input(nodes, params, "eta")?)
+                    .with_k(node_input(nodes, params, "k")?),
+                preset => return Err(params.error(&format!("unknown conductor {}", preset))),
+            };
+            let roughness = node_input_or(nodes, params, "roughness", 0.0)?;
+            let roughness_u = match params.values.contains_key("roughness_u") {
+                true => node_input(nodes, params, "roughness_u")?,
+                false => Rc::clone(&roughness),
+            };
+            let roughness_v = match params.values.contains_key("roughness_v") {
+                true => node_input(nodes, params, "roughness_v")?,
+                false => roughness,
+            };
+            conductor = conductor.with_anisotropic_roughness(roughness_u, roughness_v);
+            if params.values.contains_key("rotation") {
+                // A texture name, or a node input.
+                let rotation = match textures.get(params.string("rotation")?) {
+                    Some(image) => Rc::new(Node::Texture {
+                        image: Rc::clone(image),
+                        tiling: 1.0,
+                    }),
+                    None => node_input(nodes, params, "rotation")?,
+                };
+                conductor = conductor.with_rotation(rotation);
+            }
+            if let Some(film) = thin_film(params)? {
+                conductor = conductor.with_thin_film(film);
+            }
+            MaterialType::Conductor(conductor)
+        }
+        "rough_dielectric" => {
+            let dispersion = dispersion(params)?;
+            let mut dielectric =
+                RoughDielectric::new(index_of_refraction(params, &dispersion)?, 0.0)
+                    .with_roughness(node_input_or(nodes, params, "roughness", 0.0)?)
+                    .with_absorption(absorption(params)?);
+            if let Some(dispersion) = dispersion {
+                dielectric = dielectric.with_dispersion(dispersion);
+            }
+            MaterialType::RoughDielectric(dielectric)
+        }
+        "principled" => {
+            let mut p = Principled::new(Color::new(0.8, 0.8, 0.8));
+            let inputs = [
+                ("base_color", PrincipledInput::BaseColor),
+                ("metallic", PrincipledInput::Metallic),
+                ("roughness", PrincipledInput::Roughness),
+                ("specular", PrincipledInput::Specular),
+                ("specular_tint", PrincipledInput::SpecularTint),
+                ("sheen", PrincipledInput::Sheen),
+                ("sheen_tint", PrincipledInput::SheenTint),
+                ("clearcoat", PrincipledInput::Clearcoat),
+                ("clearcoat_gloss", PrincipledInput::ClearcoatGloss),
+                ("transmission", PrincipledInput::Transmission),
+                ("ior", PrincipledInput::Ior),
+            ];
+            for (key, input) in inputs {
+                if params.values.contains_key(key) {
+                    p = p.with_input(input, node_input(nodes, params, key)?);
+                }
+            }
+            MaterialType::Principled(p)
+        }
+        "normal_map" => MaterialType::Bumped(
+            Bumped::normal_map(
+                lookup(materials, params, "base")?,
+                lookup(textures, params, "map")?,
+            )
+            .with_strength(params.f64_or("strength", 1.0)?),
+        ),
+        "layered" => MaterialType::Layered(
+            Layered::new(
+                lookup(materials, params, "base")?,
+                params.f64_or("ior", 1.5)?,
+                0.0,
+            )
+            .with_roughness(node_input_or(nodes, params, "roughness", 0.0)?)
+            .with_absorption(node_input_or(nodes, params, "absorption", 0.0)?)
+            .with_thickness(node_input_or(nodes, params, "thickness", 1.0)?),
+        ),
+        "measured" => MaterialType::Measured(Measured::load(params.string("path")?)?),
+        "subsurface" => MaterialType::Subsurface(Subsurface::new(
+            params.color("albedo")?,
+            params.color("mfp")?,
+            params.f64_or("ior", 1.4)?,
+        )),
+        "mix" => MaterialType::Mix(Mix::new(
+            lookup(materials, params, "a")?,
+            lookup(materials, params, "b")?,
+            node_input(nodes, params, "weight")?,
+        )),
+        "masked" => MaterialType::Masked(Masked::new(
+            lookup(materials, params, "base")?,
+            lookup(textures, params, "mask")?,
+        )),
+        "bump" => MaterialType::Bumped(
+            Bumped::height_map(
+                lookup(materials, params, "base")?,
+                lookup(textures, params, "map")?,
+            )
+            .with_strength(params.f64_or("strength", 1.0)?),
+        ),
+        _ => return Err(params.error(&format!("unknown material type {}", kind))),
+    };
+    Ok(material)
+}
+
+fn parse_node(
+    kind: &str,
+    params: &Params,
+    textures: &HashMap<String, Rc<ImageTexture>>,
+    nodes: &HashMap<String, Rc<Node>>,
+) -> std::io::Result<Node> {
+    let node = match kind {
+        "constant" => Node::Constant(params.color("color")?),
+        "texture" => Node::Texture {
+            image: lookup(textures, params, "image")?,
+            tiling: params.f64_or("tiling", 1.0)?,
+        },
+        "mix" => Node::Mix {
+            a: node_input(nodes, params, "a")?,
+            b: node_input(nodes, params, "b")?,
+            factor: node_input(nodes, params, "factor")?,
+        },
+        "multiply" => Node::Multiply(
+            node_input(nodes, params, "a")?,
+            node_input(nodes, params, "b")?,
+        ),
+        "noise" => Node::Noise(Perlin::new(
+            params.f64_or("scale", 1.0)?,
+            params.f64_or("octaves", 4.0)? as u32,
+        )),
+        "fresnel" => Node::Fresnel {
+            ior: params.f64_or("ior", 1.5)?,
+        },
+        "ramp" => Node::Ramp {
+            input: node_input(nodes, params, "input")?,
+            stops: ramp_stops(params)?,
+        },
+        _ => return Err(params.error(&format!("unknown node type {}", kind))),
+    };
+    Ok(node)
+}
+
+// A node input: `@name` for a declared node, otherwise a constant given as a
+// color or a single number.
+fn node_input(
+    nodes: &HashMap<String, Rc<Node>>,
+    params: &Params,
+    key: &str,
+) -> std::io::Result<Rc<Node>> {
+    let value = params.string(key)?;
+    if let Some(name) = value.strip_prefix('@') {
+        return nodes
+            .get(name)
+            .cloned()
+            .ok_or_else(|| params.error(&format!("unknown node {}", name)));
+    }
+    if !value.contains(',') {
+        let v = params.f64(key)?;
+        return Ok(Node::constant(Color::new(v, v, v)));
+    }
+    Ok(Node::constant(params.color(key)?))
+}
+
+// Optional node input, a constant `default` when the key is absent.
+fn node_input_or(
+    nodes: &HashMap<String, Rc<Node>>,
+    params: &Params,
+    key: &str,
+    default: f64,
+) -> std::io::Result<Rc<Node>> {
+    if !params.values.contains_key(key) {
+        return Ok(Node::uniform(default));
+    }
+    node_input(nodes, params, key)
+}
+
+// Ramp stops written `position:r,g,b` and separated by semicolons.
+fn ramp_stops(params: &Params) -> std::io::Result<Vec<(f64, Color)>> {
+    let mut stops = Vec::new();
+    for stop in params.string("stops")?.split(';') {
+        let bad_stop = || params.error(&format!("bad ramp stop {}", stop));
+        let (position, color) = stop.split_once(':').ok_or_else(bad_stop)?;
+        let n = color
+            .split(',')
+            .map(|n| n.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| bad_stop())?;
+        if n.len() != 3 {
+            return Err(bad_stop());
+        }
+        let position = position.parse::<f64>().map_err(|_| bad_stop())?;
+        stops.push((position, Color::new(n[0], n[1], n[2])));
+    }
+    stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(stops)
+}
+
+// Looks up a previously declared material or texture named by `key`.
+fn lookup<T>(named: &HashMap<String, Rc<T>>, params: &Params, key: &str) -> std::io::Result<Rc<T>> {
+    let name = params.string(key)?;
+    named
+        .get(name)
+        .cloned()
+        .ok_or_else(|| params.error(&format!("unknown {} {}", key, name)))
+}
+
+// Absorption of a dielectric, either given directly or as the color white
+// light takes after travelling `tint_distance` inside the medium.
+fn absorption(params: &Params) -> std::io::Result<Color> {
+    let black = Color::new(0.0, 0.0, 0.0);
+    if params.values.contains_key("tint") {
+        let tint = params.color("tint")?;
+        let distance = params.f64_or("tint_distance", 1.0)?;
+        return Ok(Color::new(
+            -tint.r().max(1e-6).ln() / distance,
+            -tint.g().max(1e-6).ln() / distance,
+            -tint.b().max(1e-6).ln() / distance,
+        ));
+    }
+    params.color_or("absorption", black)
+}
+
+// Index of refraction, which must be given unless the glass is dispersive, as
+// `with_dispersion` then derives it from the dispersion curve.
+fn index_of_refraction(params: &Params, dispersion: &Option<Dispersion>) -> std::io::Result<f64> {
+    if dispersion.is_some() {
+        return params.f64_or("ir", 1.5);
+    }
+    params.f64("ir")
+}
+
+// Wavelength dependent index of refraction: a glass preset, Cauchy
+// coefficients `cauchy=a,b` or Sellmeier coefficients `sellmeier=b1,b2,b3,c1,c2,c3`.
+fn dispersion(params: &Params) -> std::io::Result<Option<Dispersion>> {
+    if params.values.contains_key("cauchy") {
+        let n = params.numbers("cauchy", 2)?;
+        return Ok(Some(Dispersion::Cauchy { a: n[0], b: n[1] }));
+    }
+    if params.values.contains_key("sellmeier") {
+        let n = params.numbers("sellmeier", 6)?;
+        return Ok(Some(Dispersion::Sellmeier {
+            b: [n[0], n[1], n[2]],
+            c: [n[3], n[4], n[5]],
+        }));
+    }
+    match params.string_or("glass", "") {
+        "" => Ok(None),
+        "bk7" => Ok(Some(Dispersion::bk7())),
+        "sf11" => Ok(Some(Dispersion::sf11())),
+        glass => Err(params.error(&format!("unknown glass {}", glass))),
+    }
+}
+
+// Optional thin film coating, `film_thickness` in nanometers.
+fn thin_film(params: &Params) -> std::io::Result<Option<ThinFilm>> {
+    if !params.values.contains_key("film_thickness") {
+        return Ok(None);
+    }
+    Ok(Some(ThinFilm::new(
+        params.f64("film_thickness")?,
+        params.f64_or("film_ior", 1.33)?,
+    )))
+}
+
+fn parse_light(kind: &str, params: &Params) -> std::io::Result<LightType> {
+    let light = match kind {
+        "point" => LightType::Point(PointLight::new(
+            params.vec3("position")?,
+            params.color("intensity")?,
+        )),
+        "spot" => LightType::Spot(SpotLight::new(
+            params.vec3("position")?,
+            params.vec3("target")?,
+            params.color("intensity")?,
+            params.f64_or("angle", 30.0)?,
+            params.f64_or("falloff", 25.0)?,
+        )),
+        "directional" => LightType::Directional(DirectionalLight::new(
+            params.vec3("direction")?,
+            params.color("radiance")?,
+        )),
+        _ => return Err(params.error(&format!("unknown light type {}", kind))),
+    };
+    Ok(light)
+}
+
+fn parse_environment(kind: &str, params: &Params, scene: &mut Scene) -> std::io::Result<()> {
+    let env: Rc<dyn Environment> = match kind {
+        "gradient" => {
+            let default = Gradient::default();
+            scene.environment = Rc::new(Gradient::new(
+                params.color_or("bottom", default.bottom)?,
+                params.color_or("top", default.top)?,
+            ));
+            return Ok(());
+        }
+        "map" => Rc::new(
+            EnvironmentMap::load(params.string("path")?)?
+                .with_rotation(params.f64_or("rotation", 0.0)?)
+                .with_intensity(params.f64_or("intensity", 1.0)?),
+        ),
+        "sky" => {
+            let sky = PreethamSky::new(
+                sun_direction(
+                    params.f64_or("sun_elevation", 45.0)?,
+                    params.f64_or("sun_azimuth", 0.0)?,
+                ),
+                params.f64_or("turbidity", 3.0)?,
+                params.color_or("ground_albedo", Color::new(0.3, 0.3, 0.3))?,
+            );
+            let intensity = params.f64_or("intensity", sky.intensity)?;
+            let sky = sky.with_intensity(intensity);
+            scene.add_light(
+                LightType::Sun(sky.sun_light()),
+                params.string_or("group", "default"),
+            );
+            Rc::new(sky)
+        }
+        _ => return Err(params.error(&format!("unknown environment type {}", kind))),
+    };
+
+    scene.environment = env.clone();
+    scene.add_light(
+        LightType::Environment(EnvironmentLight::new(env)),
+        params.string_or("group", "default"),
+    );
+    Ok(())
+}
+
+fn invalid(line: usize, msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line, msg))
+}
+
+// key=value arguments of a statement.
+struct Params<'a> {
+    values: HashMap<&'a str, &'a str>,
+    line: usize,
+}
+
+impl<'a> Params<'a> {
+    fn new(words: &[&'a str], line: usize) -> std::io::Result<Self> {
+        let mut values = HashMap::new();
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| invalid(line, &format!("expected key=value, got {}", word)))?;
+            values.insert(key, value);
+        }
+        Ok(Self { values, line })
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        invalid(self.line, msg)
+    }
+
+    fn string(&self, key: &str) -> std::io::Result<&'a str> {
+        self.values
+            .get(key)
+            .copied()
+            .ok_or_else(|| self.error(&format!("missing {}", key)))
+    }
+
+    fn string_or(&self, key: &str, default: &'a str) -> &'a str {
+        self.values.get(key).copied().unwrap_or(default)
+    }
+
+    fn numbers(&self, key: &str, count: usize) -> std::io::Result<Vec<f64>> {
+        let numbers = self
+            .string(key)?
+            .split(',')
+            .map(|n| n.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| self.error(&format!("bad number in {}", key)))?;
+        if numbers.len() != count {
+            return Err(self.error(&format!("expected {} values for {}", count, key)));
+        }
+        Ok(numbers)
+    }
+
+    fn f64(&self, key: &str) -> std::io::Result<f64> {
+        Ok(self.numbers(key, 1)?[0])
+    }
+
+    fn f64_or(&self, key: &str, default: f64) -> std::io::Result<f64> {
+        if !self.values.contains_key(key) {
+            return Ok(default);
+        }
+        self.f64(key)
+    }
+
+    fn vec3(&self, key: &str) -> std::io::Result<Vec3> {
+        let n = self.numbers(key, 3)?;
+        Ok(Vec3::new(n[0], n[1], n[2]))
+    }
+
+    fn color(&self, key: &str) -> std::io::Result<Color> {
+        let n = self.numbers(key, 3)?;
+        Ok(Color::new(n[0], n[1], n[2]))
+    }
+
+    fn color_or(&self, key: &str, default: Color) -> std::io::Result<Color> {
+        if !self.values.contains_key(key) {
+            return Ok(default);
+        }
+        self.color(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::PathAovs;
+    use crate::camera::Camera;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::integrator;
+    use crate::light::LightSampling;
+    use crate::material::LightReflection;
+    use crate::ray::Ray;
+    use crate::sampler::IndependentSampler;
+    use crate::spectrum;
+
+    // Material of the object seen straight down the -z axis.
+    fn material_ahead(scene: &Scene) -> Rc<MaterialType> {
+        let (origin, dir) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        let (is_hit, m) = scene
+            .world
+            .hit(&Ray::new(&origin, &dir), 0.001, f64::INFINITY, &mut rec);
+        assert!(is_hit);
+        m
+    }
+
+    fn error_message(source: &str) -> String {
+        match parse(source) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        let source = "# comment\n\nmaterial m lambertian albedo=1,1,1\nsphere center=0,0,-1 radius=0.5 material=missing\n";
+        assert!(error_message(source).starts_with("line 4:"));
+        assert!(error_message("bogus statement").starts_with("line 1: unknown statement"));
+        assert!(error_message("material m lambertian albedo=1,1").contains("line 1"));
+        assert!(error_message("sphere center=0,0,-1 radius").contains("expected key=value"));
+        assert!(error_message("material g dielectric ior=1.5").contains("missing ir"));
+    }
+
+    #[test]
+    fn shipped_scenes_load() {
+        for entry in fs::read_dir("scenes").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "scene") {
+                let path = path.to_str().unwrap();
+                if let Err(e) = load(path) {
+                    panic!("{}: {}", path, e);
+                }
+            }
+        }
+    }
+
+    // Renders a few pixels of every shipped scene with each integrator, in
+    // both color modes, catching scenes that load but can't be rendered.
+    #[test]
+    fn shipped_scenes_render() {
+        let cam = Camera::new(90.0, 16.0 / 9.0);
+        for entry in fs::read_dir("scenes").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|e| e != "scene") {
+                continue;
+            }
+            let scene = load(path.to_str().unwrap()).unwrap();
+            for name in integrator::NAMES {
+                let integrator = integrator::by_name(name, 8, None).unwrap();
+                for j in 0..4 {
+                    for i in 0..4 {
+                        let dir = cam.get_dir(i as f64 / 3.0, j as f64 / 3.0);
+                        let wavelength = (i + j) % 2 == 1;
+                        let wavelength = wavelength.then(|| spectrum::sample_wavelength().0);
+                        let r = Ray::with_wavelength(&cam.origin, &dir, wavelength);
+                        let mut aovs = PathAovs::new(scene.group_names.len());
+                        let c =
+                            integrator.li_with_aovs(&r, &scene, &mut IndependentSampler, &mut aovs);
+                        assert!(
+                            c.r().is_finite() && c.g().is_finite() && c.b().is_finite(),
+                            "{} with {}",
+                            path.display(),
+                            name
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn node_references_feed_materials() {
+        let scene = parse(
+            "node tint constant color=0.2,0.4,0.6\n\
+             material m lambertian albedo=@tint\n\
+             sphere center=0,0,-1 radius=0.5 material=m\n",
+        )
+        .unwrap();
+        let albedo = material_ahead(&scene).get_albedo();
+        assert_eq!((albedo.r(), albedo.g(), albedo.b()), (0.2, 0.4, 0.6));
+
+        let message = error_message("material m lambertian albedo=@missing\n");
+        assert!(message.contains("unknown node missing"));
+    }
+
+    #[test]
+    fn surface_parameters_take_nodes() {
+        let noise = Rc::new(Node::Noise(Perlin::new(1.0, 1)));
+        let nodes = HashMap::from([("noise".to_string(), Rc::clone(&noise))]);
+        let material = |line: &str| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let params = Params::new(&words[1..], 1).unwrap();
+            parse_material(words[0], &params, &HashMap::new(), &HashMap::new(), &nodes).unwrap()
+        };
+        let is_noise = |node: &Rc<Node>| Rc::ptr_eq(node, &noise);
+
+        let keys = "base_color metallic roughness specular specular_tint sheen sheen_tint \
+                    clearcoat clearcoat_gloss transmission ior";
+        let line = keys.split(' ').fold("principled".to_string(), |line, key| {
+            format!("{} {}=@noise", line, key)
+        });
+        match material(&line) {
+            MaterialType::Principled(p) => {
+                for node in [
+                    &p.base_color,
+                    &p.metallic,
+                    &p.roughness,
+                    &p.specular,
+                    &p.specular_tint,
+                    &p.sheen,
+                    &p.sheen_tint,
+                    &p.clearcoat,
+                    &p.clearcoat_gloss,
+                    &p.transmission,
+                    &p.ior,
+                ] {
+                    assert!(is_noise(node));
+                }
+            }
+            _ => panic!("expected a principled material"),
+        }
+
+        match material("conductor eta=@noise k=2 roughness_v=@noise") {
+            MaterialType::Conductor(c) => {
+                assert!(is_noise(&c.eta) && is_noise(&c.roughness_v));
+                assert_eq!(c.roughness_u.as_constant().unwrap().r(), 0.0);
+            }
+            _ => panic!("expected a conductor"),
+        }
+        match material("translucent reflectance=@noise transmittance=0.5") {
+            MaterialType::Translucent(t) => assert!(is_noise(&t.reflectance)),
+            _ => panic!("expected a translucent material"),
+        }
+
+        // Only a constant zero roughness makes a mirror.
+        assert!(material("conductor preset=gold").is_specular());
+        assert!(!material("conductor preset=gold roughness=@noise").is_specular());
+        assert!(material("rough_dielectric ir=1.5").is_specular());
+        assert!(!material("rough_dielectric ir=1.5 roughness=@noise").is_specular());
+    }
+
+    #[test]
+    fn emissive_mixes_are_weighted_lights() {
+        let scene = parse(
+            "material lamp light emit=4,4,4\n\
+             material black lambertian albedo=0,0,0\n\
+             material m mix a=lamp b=black weight=0.25\n\
+             sphere center=0,0,-1 radius=0.5 material=m\n",
+        )
+        .unwrap();
+        assert_eq!(scene.lights().len(), 1);
+        let m = material_ahead(&scene);
+        assert!(scene.lights()[0].owns(&m));
+
+        let mut rec = HitRecord::new();
+        rec.front_face = true;
+        assert_eq!(m.emitted(&rec).r(), 3.0);
+
+        // Mesh triangles with the same material are lights too.
+        let path = std::env::temp_dir().join(format!("mix_mesh_{}.obj", std::process::id()));
+        fs::write(&path, "v 0 0 -1\nv 1 0 -1\nv 0 1 -1\nf 1 2 3\n").unwrap();
+        let scene = parse(&format!(
+            "material lamp light emit=4,4,4\n\
+             material black lambertian albedo=0,0,0\n\
+             material m mix a=lamp b=black weight=0.25\n\
+             mesh path={} material=m group=key\n",
+            path.to_string_lossy()
+        ));
+        fs::remove_file(&path).unwrap();
+        let scene = scene.unwrap();
+        assert_eq!(scene.lights().len(), 1);
+        assert_eq!(scene.group_names[scene.light_group(0)], "key");
+    }
+
+    #[test]
+    fn emissive_triangles_are_lights() {
+        let scene = parse(
+            "material lamp light emit=4,4,4\n\
+             material grey lambertian albedo=0.5,0.5,0.5\n\
+             triangle v0=-1,-1,-1 v1=1,-1,-1 v2=0,1,-1 material=lamp group=key\n\
+             triangle v0=-1,-1,-2 v1=1,-1,-2 v2=0,1,-2 material=grey group=fill\n",
+        )
+        .unwrap();
+        assert_eq!(scene.lights().len(), 1);
+        assert_eq!(scene.group_names[scene.light_group(0)], "key");
+        assert!(scene.lights()[0].owns(&material_ahead(&scene)));
+    }
+
+    #[test]
+    fn ramp_stops_are_sorted_and_validated() {
+        let params = Params::new(&["stops=1:1,1,1;0:0,0,0;0.5:1,0,0"], 1).unwrap();
+        let stops = ramp_stops(&params).unwrap();
+        let positions: Vec<f64> = stops.iter().map(|s| s.0).collect();
+        assert_eq!(positions, vec![0.0, 0.5, 1.0]);
+        assert_eq!(stops[1].1.r(), 1.0);
+
+        for bad in ["stops=0.5:1,1", "stops=0.5", "stops=x:1,1,1"] {
+            let params = Params::new(&[bad], 7).unwrap();
+            let message = ramp_stops(&params).err().unwrap().to_string();
+            assert!(message.starts_with("line 7: bad ramp stop"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn masks_keep_light_pdfs_steady_and_pass_through_mixes() {
+        let grey = |v: f64| Rc::new(ImageTexture::new(1, 1, vec![Color::new(v, v, v)]));
+        let lamp = Rc::new(MaterialType::DiffuseLight(DiffuseLight::new(Color::new(
+            4.0, 4.0, 4.0,
+        ))));
+        let half = Rc::new(MaterialType::Masked(Masked::new(
+            Rc::clone(&lamp),
+            grey(0.5),
+        )));
+        let sphere = Rc::new(Sphere::new(Vec3::new(0.0, 0.0, -2.0), 0.5, half));
+        let light = LightType::Area(AreaLight::new(Rc::clone(&sphere)));
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        let wi = Vec3::new(0.0, 0.0, -1.0);
+        let pdf = light.pdf_li(&origin, &wi);
+        assert!(pdf > 0.0);
+        for _ in 0..100 {
+            assert_eq!(light.pdf_li(&origin, &wi), pdf);
+        }
+
+        // A mix choosing a fully cut out material lets every ray through.
+        let hole = Rc::new(MaterialType::Masked(Masked::new(lamp, grey(0.0))));
+        let black = Rc::new(MaterialType::Lambertian(Lambertian::new(Color::new(
+            0.0, 0.0, 0.0,
+        ))));
+        let mix = MaterialType::Mix(Mix::new(black, hole, Node::uniform(1.0)));
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        assert!(mix.passes_through(&rec));
+    }
+
+    #[test]
+    fn wrapped_subsurface_keeps_its_medium() {
+        let skin = Rc::new(MaterialType::Subsurface(Subsurface::new(
+            Color::new(0.8, 0.5, 0.4),
+            Color::new(1.0, 0.5, 0.25),
+            1.4,
+        )));
+        let map = Rc::new(ImageTexture::new(1, 1, vec![Color::new(0.5, 0.5, 0.5)]));
+        let wrapped = [
+            MaterialType::Bumped(Bumped::height_map(Rc::clone(&skin), Rc::clone(&map))),
+            MaterialType::Masked(Masked::new(Rc::clone(&skin), map)),
+            MaterialType::Layered(Layered::new(skin, 1.5, 0.0)),
+        ];
+        for m in wrapped {
+            assert!(m.medium().is_some());
+        }
+    }
+
+    #[test]
+    fn conductor_films_follow_eta_and_k() {
+        let film = ThinFilm::new(300.0, 1.33);
+        let copper = Conductor::copper(0.0);
+        let late = Conductor::gold(0.0)
+            .with_thin_film(film)
+            .with_eta(Rc::clone(&copper.eta))
+            .with_k(Rc::clone(&copper.k));
+        let early = copper.with_thin_film(film);
+        for cos_theta in [0.1, 0.5, 1.0] {
+            let (a, b) = (
+                late.film.as_ref().unwrap().evaluate(cos_theta, None),
+                early.film.as_ref().unwrap().evaluate(cos_theta, None),
+            );
+            assert_eq!((a.r(), a.g(), a.b()), (b.r(), b.g(), b.b()));
+        }
+    }
+}
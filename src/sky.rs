@@ -0,0 +1,220 @@
+use crate::color::Color;
+use crate::environment::Environment;
+use crate::light::SunLight;
+use crate::spectrum::xyz_to_rgb;
+use crate::utils::degrees_to_radian;
+use crate::vec::Vec3;
+use std::f64::consts::PI;
+
+// Extraterrestrial solar illuminance in klux, the same unit scale as the
+// zenith luminance of the model (kcd/m^2).
+const SOLAR_ILLUMINANCE: f64 = 127.5;
+
+// Angular radius of the sun disk as seen from earth, in degrees.
+const SUN_ANGULAR_RADIUS: f64 = 0.2665;
+
+// Analytic daylight model from Preetham, Shirley and Smits, "A Practical
+// Analytic Model for Daylight" (1999). The sun disk itself is not part of the
+// sky radiance, it is provided by the matching `SunLight`.
+pub struct PreethamSky {
+    pub sun_direction: Vec3,
+    pub turbidity: f64,
+    pub ground_albedo: Color,
+    // Scale from kcd/m^2 to scene radiance units.
+    pub intensity: f64,
+    theta_sun: f64,
+    perez_y: [f64; 5],
+    perez_x: [f64; 5],
+    perez_yy: [f64; 5],
+    zenith: (f64, f64, f64),
+    ground: Color,
+}
+
+impl PreethamSky {
+    // `sun_direction` points from the scene towards the sun.
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Color) -> Self {
+        let t = turbidity;
+        let sun_direction = Vec3::unit_vector(&sun_direction);
+        let theta_sun = f64::acos(sun_direction.y().clamp(0.0, 1.0));
+
+        let perez_yy = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_y = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * f64::tan(chi) - 0.2155 * t + 2.4192;
+
+        let (t1, t2, t3) = (theta_sun, theta_sun.powi(2), theta_sun.powi(3));
+        let zenith_x = t * t * (0.00166 * t3 - 0.00375 * t2 + 0.00209 * t1)
+            + t * (-0.02903 * t3 + 0.06377 * t2 - 0.03202 * t1 + 0.00394)
+            + (0.11693 * t3 - 0.21196 * t2 + 0.06052 * t1 + 0.25886);
+        let zenith_y = t * t * (0.00275 * t3 - 0.00610 * t2 + 0.00317 * t1)
+            + t * (-0.04214 * t3 + 0.08970 * t2 - 0.04153 * t1 + 0.00516)
+            + (0.15346 * t3 - 0.26756 * t2 + 0.06670 * t1 + 0.26688);
+
+        let mut sky = Self {
+            sun_direction,
+            turbidity,
+            ground_albedo,
+            intensity: 0.05,
+            theta_sun,
+            perez_y,
+            perez_x,
+            perez_yy,
+            zenith: (zenith_x, zenith_y, f64::max(zenith_luminance, 0.0)),
+            ground: Color::new(0.0, 0.0, 0.0),
+        };
+        sky.ground = sky.ground_radiance();
+        sky
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self.ground = self.ground_radiance();
+        self
+    }
+
+    // Sun disk light matching this sky, dimmed and reddened by the atmosphere.
+    pub fn sun_light(&self) -> SunLight {
+        let irradiance = self.sun_transmittance() * (SOLAR_ILLUMINANCE * self.intensity);
+        SunLight::new(self.sun_direction, irradiance, SUN_ANGULAR_RADIUS)
+    }
+
+    fn perez(coefficients: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = *coefficients;
+        (1.0 + a * f64::exp(b / cos_theta))
+            * (1.0 + c * f64::exp(d * gamma) + e * f64::cos(gamma).powi(2))
+    }
+
+    fn sky_radiance(&self, dir: &Vec3) -> Color {
+        let cos_theta = f64::max(dir.y(), 0.001);
+        let gamma = f64::acos(dir.dot(&self.sun_direction).clamp(-1.0, 1.0));
+
+        let ratio = |coefficients: &[f64; 5]| {
+            Self::perez(coefficients, cos_theta, gamma)
+                / Self::perez(coefficients, 1.0, self.theta_sun)
+        };
+
+        let (zx, zy, zl) = self.zenith;
+        let x = zx * ratio(&self.perez_x);
+        let y = zy * ratio(&self.perez_y);
+        let luminance = zl * ratio(&self.perez_yy);
+
+        xyy_to_rgb(x, y, luminance) * self.intensity
+    }
+
+    // Transmittance of the atmosphere along the sun direction at the red,
+    // green and blue wavelengths, from Rayleigh and aerosol scattering.
+    fn sun_transmittance(&self) -> Color {
+        if self.sun_direction.y() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let theta_degrees = self.theta_sun.to_degrees();
+        let relative_mass =
+            1.0 / (f64::cos(self.theta_sun) + 0.15 * f64::powf(93.885 - theta_degrees, -1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+
+        let transmittance = |lambda_um: f64| {
+            let rayleigh = f64::exp(-0.008735 * lambda_um.powf(-4.08) * relative_mass);
+            let aerosol = f64::exp(-beta * lambda_um.powf(-1.3) * relative_mass);
+            rayleigh * aerosol
+        };
+
+        Color::new(
+            transmittance(0.680),
+            transmittance(0.550),
+            transmittance(0.440),
+        )
+    }
+
+    // Radiance of a diffuse ground plane lit by the sky hemisphere and the sun.
+    fn ground_radiance(&self) -> Color {
+        let steps_theta = 32;
+        let steps_phi = 64;
+        let d_theta = 0.5 * PI / steps_theta as f64;
+        let d_phi = 2.0 * PI / steps_phi as f64;
+
+        let mut irradiance = Color::new(0.0, 0.0, 0.0);
+        for i in 0..steps_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..steps_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let dir = Vec3::new(
+                    f64::sin(theta) * f64::cos(phi),
+                    f64::cos(theta),
+                    f64::sin(theta) * f64::sin(phi),
+                );
+                let weight = f64::cos(theta) * f64::sin(theta) * d_theta * d_phi;
+                irradiance += self.sky_radiance(&dir) * weight;
+            }
+        }
+
+        irradiance += self.sun_transmittance()
+            * (SOLAR_ILLUMINANCE * self.intensity * f64::max(self.sun_direction.y(), 0.0));
+
+        self.ground_albedo * irradiance * (1.0 / PI)
+    }
+}
+
+impl Environment for PreethamSky {
+    fn radiance(&self, dir: &Vec3) -> Color {
+        let dir = Vec3::unit_vector(dir);
+        if dir.y() < 0.0 {
+            return self.ground;
+        }
+        self.sky_radiance(&dir)
+    }
+
+    // The sky is smooth, uniform sampling of the sphere is enough.
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        Some((Vec3::random_unit_vector(), 1.0 / (4.0 * PI)))
+    }
+
+    fn pdf(&self, _dir: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+// Converts CIE xyY chromaticity and luminance to linear sRGB.
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let c = xyz_to_rgb(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+    Color::new(
+        f64::max(c.r(), 0.0),
+        f64::max(c.g(), 0.0),
+        f64::max(c.b(), 0.0),
+    )
+}
+
+// Builds a sun direction from its elevation above the horizon and its
+// azimuth around the y axis, both in degrees.
+pub fn sun_direction(elevation: f64, azimuth: f64) -> Vec3 {
+    let elevation = degrees_to_radian(elevation);
+    let azimuth = degrees_to_radian(azimuth);
+    Vec3::new(
+        f64::cos(elevation) * f64::sin(azimuth),
+        f64::sin(elevation),
+        -f64::cos(elevation) * f64::cos(azimuth),
+    )
+}
@@ -0,0 +1,185 @@
+use crate::color::Color;
+use crate::utils::random_boundaries;
+use std::sync::OnceLock;
+
+// Visible range covered by spectral rendering, in nanometers.
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 720.0;
+
+// Basis spectra from Smits, "An RGB to Spectrum Conversion for Reflectances"
+// (1999), sampled at ten evenly spaced bins over the visible range.
+const WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// Picks the wavelength carried by a camera path, returning it with its pdf.
+pub fn sample_wavelength() -> (f64, f64) {
+    (
+        random_boundaries(LAMBDA_MIN, LAMBDA_MAX),
+        1.0 / (LAMBDA_MAX - LAMBDA_MIN),
+    )
+}
+
+// Value at `lambda` of a smooth spectrum whose color is `c`.
+pub fn rgb_to_spectrum(c: &Color, lambda: f64) -> f64 {
+    let (r, g, b) = (c.r(), c.g(), c.b());
+    let basis = |spectrum: &[f64; 10]| basis_value(spectrum, lambda);
+
+    if r <= g && r <= b {
+        let mut value = r * basis(&WHITE);
+        if g <= b {
+            value += (g - r) * basis(&CYAN) + (b - g) * basis(&BLUE);
+        } else {
+            value += (b - r) * basis(&CYAN) + (g - b) * basis(&GREEN);
+        }
+        value
+    } else if g <= r && g <= b {
+        let mut value = g * basis(&WHITE);
+        if r <= b {
+            value += (r - g) * basis(&MAGENTA) + (b - r) * basis(&BLUE);
+        } else {
+            value += (b - g) * basis(&MAGENTA) + (r - b) * basis(&RED);
+        }
+        value
+    } else {
+        let mut value = b * basis(&WHITE);
+        if r <= g {
+            value += (r - b) * basis(&YELLOW) + (g - r) * basis(&GREEN);
+        } else {
+            value += (g - b) * basis(&YELLOW) + (r - g) * basis(&RED);
+        }
+        value
+    }
+}
+
+// Linear interpolation between the bin centers of a basis spectrum.
+fn basis_value(spectrum: &[f64; 10], lambda: f64) -> f64 {
+    let bin_width = (LAMBDA_MAX - LAMBDA_MIN) / spectrum.len() as f64;
+    let x = ((lambda - LAMBDA_MIN) / bin_width - 0.5).clamp(0.0, spectrum.len() as f64 - 1.0);
+    let i = usize::min(x as usize, spectrum.len() - 2);
+    let t = x - i as f64;
+    spectrum[i] * (1.0 - t) + spectrum[i + 1] * t
+}
+
+// CIE 1931 color matching functions, from the multi-lobe fit of Wyman, Sloan
+// and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching
+// Functions" (2013).
+pub fn cie_xyz(lambda: f64) -> (f64, f64, f64) {
+    let g = |mu: f64, sigma1: f64, sigma2: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+        f64::exp(-0.5 * t * t)
+    };
+
+    let x =
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+// Converts CIE XYZ to linear sRGB, without clamping out of gamut values.
+pub fn xyz_to_rgb(x: f64, y: f64, z: f64) -> Color {
+    Color::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+// Integral of the y matching function over the visible range and the color
+// of the equal energy spectrum, used to map a white spectrum back to white.
+fn normalization() -> &'static (f64, Color) {
+    static NORMALIZATION: OnceLock<(f64, Color)> = OnceLock::new();
+    NORMALIZATION.get_or_init(|| {
+        let steps = 1000;
+        let d_lambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for i in 0..steps {
+            let (cx, cy, cz) = cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * d_lambda);
+            x += cx * d_lambda;
+            y += cy * d_lambda;
+            z += cz * d_lambda;
+        }
+        (y, xyz_to_rgb(x / y, 1.0, z / y))
+    })
+}
+
+// Monte Carlo estimate of the color of a spectrum from a single sample of
+// value `value` at wavelength `lambda`, drawn with pdf `pdf`.
+pub fn spectral_to_rgb(value: f64, lambda: f64, pdf: f64) -> Color {
+    let (y_integral, white) = normalization();
+    let (x, y, z) = cie_xyz(lambda);
+    let scale = value / (pdf * y_integral);
+    let c = xyz_to_rgb(x * scale, y * scale, z * scale);
+    Color::new(c.r() / white.r(), c.g() / white.g(), c.b() / white.b())
+}
+
+// Color of a spectrum known as a function of wavelength, integrated with
+// evenly spaced samples over the visible range.
+pub fn spectrum_to_rgb(f: impl Fn(f64) -> f64) -> Color {
+    let steps = 32;
+    let d_lambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    let mut color = Color::new(0.0, 0.0, 0.0);
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + (i as f64 + 0.5) * d_lambda;
+        color += spectral_to_rgb(f(lambda), lambda, 1.0 / d_lambda);
+    }
+    color
+}
+
+// Index of refraction varying with wavelength.
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    // n = a + b / lambda^2, lambda in micrometers.
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum of b_i lambda^2 / (lambda^2 - c_i), lambda in micrometers.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    // Borosilicate crown glass.
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    // Dense flint glass, with strong dispersion.
+    pub fn sf11() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        }
+    }
+
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let l = lambda / 1000.0;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                f64::sqrt(1.0 + sum)
+            }
+        }
+    }
+}
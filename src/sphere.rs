@@ -1,29 +1,63 @@
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::MaterialType;
+use crate::onb::Onb;
 use crate::ray::Ray;
 use crate::vec::Vec3;
+use std::f64::consts::PI;
 use std::rc::Rc;
 
-pub struct Sphere<'a> {
-    pub center: &'a Vec3,
+pub struct Sphere {
+    pub center: Vec3,
     pub radius: f64,
     pub m: Rc<MaterialType>,
 }
 
-impl<'a> Sphere<'a> {
-    pub fn new(center: &'a Vec3, radius: f64, m: Rc<MaterialType>) -> Self {
+impl Sphere {
+    pub fn new(center: Vec3, radius: f64, m: Rc<MaterialType>) -> Self {
         Self { center, radius, m }
     }
-}
 
-impl<'a> Hittable for Sphere<'a> {
-    fn hit(
-        &self,
-        r: &Ray,
-        t_min: f64,
-        t_max: f64,
-        rec: &mut HitRecord,
-    ) -> (bool, Rc<MaterialType>) {
+    // Samples a direction from `origin` towards the cone subtended by the sphere.
+    // Returns the direction and its solid angle pdf. From inside the sphere
+    // every direction hits it, and they're sampled uniformly.
+    pub fn sample_direction(&self, origin: &Vec3) -> (Vec3, f64) {
+        let direction = self.center - origin;
+        let distance_squared = direction.norm_squared();
+        if distance_squared <= self.radius * self.radius {
+            return (Vec3::random_unit_vector(), 1.0 / (4.0 * PI));
+        }
+        let cos_theta_max = f64::sqrt(1.0 - self.radius * self.radius / distance_squared);
+
+        let uvw = Onb::build_from_w(&direction);
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        (
+            uvw.local_vec(&Vec3::random_to_cone(cos_theta_max)),
+            1.0 / solid_angle,
+        )
+    }
+
+    // The geometry alone is tested, an opacity mask would make the pdf random.
+    pub fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        let hits = self
+            .roots(&Ray::new(origin, direction))
+            .is_some_and(|roots| roots.iter().any(|&t| t >= 0.001));
+        if !hits {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center - origin).norm_squared();
+        if distance_squared <= self.radius * self.radius {
+            return 1.0 / (4.0 * PI);
+        }
+        let cos_theta_max = f64::sqrt(1.0 - self.radius * self.radius / distance_squared);
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
+    }
+
+    // Distances along `r` to the sphere, nearest first.
+    fn roots(&self, r: &Ray) -> Option<[f64; 2]> {
         let oc = r.origin() - self.center;
 
         let a = r.direction().norm().powf(2.0);
@@ -33,26 +67,60 @@ impl<'a> Hittable for Sphere<'a> {
         let discrimant = half_b.powf(2.0) - a * c;
 
         if discrimant < 0.0 {
-            return (false, Rc::clone(&self.m));
+            return None;
         }
         let sqrtd = discrimant.sqrt();
+        Some([(-half_b - sqrtd) / a, (-half_b + sqrtd) / a])
+    }
+}
 
-        // Find the nearest root that lies in the acceptable range.
-        let mut root = (-half_b - sqrtd) / a;
+impl Hittable for Sphere {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+    ) -> (bool, Rc<MaterialType>) {
+        let roots = match self.roots(r) {
+            Some(roots) => roots,
+            None => return (false, Rc::clone(&self.m)),
+        };
 
-        if root < t_min || root > t_max {
-            root = (-half_b + sqrtd) / a;
+        // Take the nearest root in the acceptable range whose point isn't
+        // masked out by the material, the far side shows through holes.
+        for root in roots {
             if root < t_min || root > t_max {
-                return (false, Rc::clone(&self.m));
-            };
-        }
+                continue;
+            }
+
+            let mut candidate = HitRecord::new();
+            candidate.t = root;
+            candidate.p = r.at(candidate.t);
 
-        rec.t = root;
-        rec.p = r.at(rec.t);
+            let outward_normal = (candidate.p - self.center) / self.radius;
+            candidate.set_face_normal(r, &outward_normal);
 
-        let outward_normal = (&rec.p - self.center) / self.radius;
-        rec.set_face_normal(r, &outward_normal);
+            // Longitude / latitude parameterization, v = 0 at the bottom pole.
+            let theta = f64::acos((-outward_normal.y()).clamp(-1.0, 1.0));
+            let phi = f64::atan2(-outward_normal.z(), outward_normal.x()) + PI;
+            candidate.u = phi / (2.0 * PI);
+            candidate.v = theta / PI;
+            let dpdu = Vec3::new(outward_normal.z(), 0.0, -outward_normal.x());
+            let dpdv = Vec3::new(
+                -f64::cos(phi) * f64::cos(theta),
+                f64::sin(theta),
+                f64::sin(phi) * f64::cos(theta),
+            );
+            candidate.set_tangent_frame(&outward_normal, &dpdu, &dpdv);
+
+            if self.m.passes_through(&candidate) {
+                continue;
+            }
+            *rec = candidate;
+            return (true, Rc::clone(&self.m));
+        }
 
-        (true, Rc::clone(&self.m))
+        (false, Rc::clone(&self.m))
     }
 }
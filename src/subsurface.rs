@@ -0,0 +1,100 @@
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::material::{Dielectric, LightReflection};
+use crate::ray::Ray;
+use crate::utils::random_number;
+use crate::vec::Vec3;
+
+// Homogeneous medium with isotropic scattering, coefficients per unit length.
+#[derive(Clone, Copy)]
+pub struct Medium {
+    pub sigma_s: Color,
+    pub sigma_t: Color,
+}
+
+impl Medium {
+    pub fn new(sigma_s: Color, sigma_t: Color) -> Self {
+        Self { sigma_s, sigma_t }
+    }
+
+    // Medium whose multiple scattering albedo is `albedo` when viewed as a
+    // whole, using the fit from Chiang et al. 2016 to find the single
+    // scattering albedo.
+    pub fn from_albedo(albedo: &Color, mean_free_path: &Color) -> Self {
+        let single = |a: f64| {
+            let a = a.clamp(0.0, 0.999);
+            let s = 4.09712 + 4.20863 * a - f64::sqrt(9.59217 + 41.6808 * a + 17.7126 * a * a);
+            1.0 - s * s
+        };
+        let sigma_t = Color::new(
+            1.0 / mean_free_path.r().max(1e-6),
+            1.0 / mean_free_path.g().max(1e-6),
+            1.0 / mean_free_path.b().max(1e-6),
+        );
+        let sigma_s = Color::new(
+            single(albedo.r()) * sigma_t.r(),
+            single(albedo.g()) * sigma_t.g(),
+            single(albedo.b()) * sigma_t.b(),
+        );
+        Self::new(sigma_s, sigma_t)
+    }
+
+    // Samples where a ray travelling through the medium interacts, given the
+    // distance to the next surface. Returns the distance of the scattering
+    // event, or None when the ray reaches the surface, with the path weight.
+    // Distances are drawn from one channel picked at random and weighted over
+    // all of them.
+    pub fn sample(&self, distance: f64, wavelength: Option<f64>) -> (Option<f64>, Color) {
+        let sigma_s = self.sigma_s.at_wavelength(wavelength);
+        let sigma_t = self.sigma_t.at_wavelength(wavelength);
+        let channels = [sigma_t.r(), sigma_t.g(), sigma_t.b()];
+
+        let channel = usize::min((random_number() * 3.0) as usize, 2);
+        let t = -f64::ln(1.0 - random_number()) / channels[channel];
+        let transmittance = |t: f64| (sigma_t * -t).exp();
+
+        if t < distance {
+            let tr = transmittance(t);
+            let pdf = (channels[0] * tr.r() + channels[1] * tr.g() + channels[2] * tr.b()) / 3.0;
+            (Some(t), sigma_s * tr / pdf)
+        } else {
+            let tr = transmittance(distance);
+            let pdf = (tr.r() + tr.g() + tr.b()) / 3.0;
+            (None, tr / pdf)
+        }
+    }
+
+    pub fn sample_phase(&self) -> Vec3 {
+        Vec3::random_unit_vector()
+    }
+}
+
+// Translucent material scattering light inside the object: a smooth dielectric
+// boundary around a scattering medium. The integrator random walks through the
+// medium until the path leaves the object again. The medium is homogeneous, so
+// the albedo is a single color rather than a node.
+pub struct Subsurface {
+    pub albedo: Color,
+    pub boundary: Dielectric,
+    pub medium: Medium,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Color, mean_free_path: Color, ior: f64) -> Self {
+        Self {
+            albedo,
+            boundary: Dielectric::new(ior),
+            medium: Medium::from_albedo(&albedo, &mean_free_path),
+        }
+    }
+}
+
+impl LightReflection for Subsurface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> (bool, Vec3) {
+        self.boundary.scatter(r_in, rec, attenuation)
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
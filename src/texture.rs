@@ -0,0 +1,162 @@
+use crate::color::Color;
+use crate::environment::read_hdr;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+// Image looked up by surface (u, v) coordinates, wrapping around in both
+// directions. Values are used as stored, without any gamma decoding, so the
+// same loader serves color, normal and height maps.
+pub struct ImageTexture {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Color>,
+    // Average of the pixels, computed once as shading asks for it per hit.
+    mean: Color,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        let sum = pixels
+            .iter()
+            .fold(Color::new(0.0, 0.0, 0.0), |sum, p| sum + *p);
+        Self {
+            width,
+            height,
+            mean: sum / pixels.len().max(1) as f64,
+            pixels,
+        }
+    }
+
+    pub fn mean(&self) -> Color {
+        self.mean
+    }
+
+    // Single color over the whole surface.
+    pub fn constant(color: Color) -> Self {
+        Self::new(1, 1, vec![color])
+    }
+
+    // Loads a Radiance .hdr or a binary / ascii .ppm image.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let data = fs::read(path)?;
+        let (width, height, pixels) = if path.ends_with(".hdr") {
+            read_hdr(&data)?
+        } else {
+            read_ppm(&data)?
+        };
+        Ok(Self::new(width, height, pixels))
+    }
+
+    // Decodes sRGB encoded values, as stored by color images, to linear.
+    pub fn srgb_to_linear(self) -> Self {
+        let decode = |c: f64| {
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                f64::powf((c + 0.055) / 1.055, 2.4)
+            }
+        };
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|p| Color::new(decode(p.r()), decode(p.g()), decode(p.b())))
+            .collect();
+        Self::new(self.width, self.height, pixels)
+    }
+
+    // Bilinearly filtered lookup, v = 0 is the bottom row of the image.
+    pub fn value(&self, u: f64, v: f64) -> Color {
+        let x = u.rem_euclid(1.0) * self.width as f64 - 0.5;
+        let y = (1.0 - v.rem_euclid(1.0)) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let texel = |i: f64, j: f64| -> Color {
+            let i = (i as i64).rem_euclid(self.width as i64) as usize;
+            let j = (j as i64).rem_euclid(self.height as i64) as usize;
+            self.pixels[j * self.width + i]
+        };
+
+        let top = texel(x0, y0) * (1.0 - tx) + texel(x0 + 1.0, y0) * tx;
+        let bottom = texel(x0, y0 + 1.0) * (1.0 - tx) + texel(x0 + 1.0, y0 + 1.0) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+// Decodes a P3 (ascii) or P6 (binary) portable pixmap into [0, 1] values.
+fn read_ppm(data: &[u8]) -> std::io::Result<(usize, usize, Vec<Color>)> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+    // Header fields are whitespace separated and may be interleaved with comments.
+    let mut pos = 0;
+    let mut next_token = || -> Option<String> {
+        loop {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            break;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        (pos > start).then(|| String::from_utf8_lossy(&data[start..pos]).into_owned())
+    };
+
+    let magic = next_token().ok_or_else(|| invalid("empty ppm file"))?;
+    let mut number = |name: &str| -> std::io::Result<usize> {
+        next_token()
+            .and_then(|t| t.parse().ok())
+            .ok_or_else(|| invalid(&format!("bad ppm {}", name)))
+    };
+    let width = number("width")?;
+    let height = number("height")?;
+    let max_value = number("max value")?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid("bad ppm max value"));
+    }
+    let scale = 1.0 / max_value as f64;
+
+    let count = width * height * 3;
+    let samples: Vec<usize> = match magic.as_str() {
+        "P3" => (0..count)
+            .map(|_| number("sample"))
+            .collect::<std::io::Result<_>>()?,
+        "P6" => {
+            // A single whitespace byte separates the header from the raster.
+            let start = pos + 1;
+            let bytes = if max_value < 256 { 1 } else { 2 };
+            if start + count * bytes > data.len() {
+                return Err(invalid("truncated ppm data"));
+            }
+            let raster = &data[start..start + count * bytes];
+            if bytes == 1 {
+                raster.iter().map(|&b| b as usize).collect()
+            } else {
+                raster
+                    .chunks(2)
+                    .map(|b| (b[0] as usize) << 8 | b[1] as usize)
+                    .collect()
+            }
+        }
+        _ => return Err(invalid("unsupported ppm format")),
+    };
+
+    let pixels = samples
+        .chunks(3)
+        .map(|s| {
+            Color::new(
+                s[0] as f64 * scale,
+                s[1] as f64 * scale,
+                s[2] as f64 * scale,
+            )
+        })
+        .collect();
+    Ok((width, height, pixels))
+}
@@ -0,0 +1,142 @@
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::MaterialType;
+use crate::ray::Ray;
+use crate::vec::Vec3;
+use std::rc::Rc;
+
+pub struct Triangle {
+    pub v0: Vec3,
+    pub v1: Vec3,
+    pub v2: Vec3,
+    // Texture coordinates of each vertex.
+    pub uvs: [(f64, f64); 3],
+    // Vertex normals interpolated for smooth shading.
+    pub normals: Option<[Vec3; 3]>,
+    pub m: Rc<MaterialType>,
+}
+
+impl Triangle {
+    pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, m: Rc<MaterialType>) -> Self {
+        Self {
+            v0,
+            v1,
+            v2,
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            normals: None,
+            m,
+        }
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Self {
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    // Surface derivatives along u and v, from the edges and their uv deltas.
+    fn uv_derivatives(&self) -> Option<(Vec3, Vec3)> {
+        let (du02, dv02) = (self.uvs[0].0 - self.uvs[2].0, self.uvs[0].1 - self.uvs[2].1);
+        let (du12, dv12) = (self.uvs[1].0 - self.uvs[2].0, self.uvs[1].1 - self.uvs[2].1);
+        let determinant = du02 * dv12 - dv02 * du12;
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let dp02 = self.v0 - self.v2;
+        let dp12 = self.v1 - self.v2;
+        let inv = 1.0 / determinant;
+        Some((
+            (dp02 * dv12 - dp12 * dv02) * inv,
+            (dp12 * du02 - dp02 * du12) * inv,
+        ))
+    }
+}
+
+impl Triangle {
+    // Moller-Trumbore ray / triangle intersection of the geometry alone,
+    // ignoring any opacity mask. Returns the distance and the barycentric
+    // coordinates of the second and third vertices.
+    pub fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let edge1 = self.v1 - self.v0;
+        let edge2 = self.v2 - self.v0;
+        let pvec = r.direction().cross(&edge2);
+        let det = edge1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin() - self.v0;
+        let b1 = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(&edge1);
+        let b2 = r.direction().dot(&qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(&qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        Some((t, b1, b2))
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+    ) -> (bool, Rc<MaterialType>) {
+        let (t, b1, b2) = match self.intersect(r, t_min, t_max) {
+            Some(hit) => hit,
+            None => return (false, Rc::clone(&self.m)),
+        };
+        let edge1 = self.v1 - self.v0;
+        let edge2 = self.v2 - self.v0;
+
+        let mut candidate = HitRecord::new();
+        candidate.t = t;
+        candidate.p = r.at(t);
+        let b0 = 1.0 - b1 - b2;
+        let geometric_normal = Vec3::unit_vector(&edge1.cross(&edge2));
+        let outward_normal = match &self.normals {
+            Some([n0, n1, n2]) => {
+                // Keep the shading normal on the side of the geometric one.
+                let n = Vec3::unit_vector(&(n0 * b0 + n1 * b1 + n2 * b2));
+                if n.dot(&geometric_normal) < 0.0 {
+                    -n
+                } else {
+                    n
+                }
+            }
+            None => geometric_normal,
+        };
+        candidate.set_face_normal(r, &geometric_normal);
+        candidate.normal = if candidate.front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+
+        candidate.u = b0 * self.uvs[0].0 + b1 * self.uvs[1].0 + b2 * self.uvs[2].0;
+        candidate.v = b0 * self.uvs[0].1 + b1 * self.uvs[1].1 + b2 * self.uvs[2].1;
+        let (dpdu, dpdv) = self
+            .uv_derivatives()
+            .unwrap_or((edge1, outward_normal.cross(&edge1)));
+        candidate.set_tangent_frame(&outward_normal, &dpdu, &dpdv);
+
+        if self.m.passes_through(&candidate) {
+            return (false, Rc::clone(&self.m));
+        }
+        *rec = candidate;
+        (true, Rc::clone(&self.m))
+    }
+}
@@ -1,3 +1,4 @@
+use crate::sampler;
 use rand::{self, Rng};
 use std::f64::consts::PI;
 // Constants
@@ -5,7 +6,14 @@ pub fn degrees_to_radian(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+// Every random decision taken while tracing a path, by materials, lights,
+// media and the integrators' samplers alike, must come from here: Metropolis
+// rendering replays a path from the numbers it consumed, so a number drawn
+// anywhere else would break the replay.
 pub fn random_number() -> f64 {
+    if let Some(u) = sampler::next_sample() {
+        return u;
+    }
     let mut rng = rand::thread_rng();
 
     rng.gen()
@@ -23,3 +31,13 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     }
     x
 }
+
+// Multiple importance sampling weight for a sample drawn from the first strategy.
+pub fn power_heuristic(pdf_f: f64, pdf_g: f64) -> f64 {
+    let f = pdf_f * pdf_f;
+    let g = pdf_g * pdf_g;
+    if f + g == 0.0 {
+        return 0.0;
+    }
+    f / (f + g)
+}
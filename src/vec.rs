@@ -1,6 +1,7 @@
 use crate::utils::{random_boundaries, random_number};
 
 use std::{
+    f64::consts::PI,
     fmt::Display,
     ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
 };
@@ -99,6 +100,17 @@ impl Vec3 {
         -in_unit_sphere
     }
 
+    // Random direction inside a cone around +z, uniform in solid angle.
+    pub fn random_to_cone(cos_theta_max: f64) -> Self {
+        let r1 = random_number();
+        let r2 = random_number();
+        let z = 1.0 + r2 * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * r1;
+        let sin_theta = f64::sqrt(1.0 - z * z);
+
+        Self::new(f64::cos(phi) * sin_theta, f64::sin(phi) * sin_theta, z)
+    }
+
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
         (self.x.abs() < s) && (self.y.abs() < s) && (self.z.abs() < s)